@@ -0,0 +1,191 @@
+use anchor_lang::prelude::*;
+use swap_escrow::program::SwapEscrow;
+
+declare_id!("3EV85H1spn6e9uaQsTVdnPWNE5rBpBrmin8Nxvrbc12Q");
+
+/// Test-only program standing in for a marketplace that drives swap_escrow through CPI.
+/// Its `depositor` PDA is the escrow party and signs with `invoke_signed`; its `settler` PDA
+/// settles escrows on behalf of an aggregator.
+#[program]
+pub mod escrow_cpi_tester {
+    use super::*;
+
+    pub fn deposit(ctx: Context<DepositViaCpi>, is_initializer: bool, nft_index: u8) -> Result<()> {
+        let bump = [ctx.bumps.depositor];
+        let seeds: &[&[u8]] = &[b"depositor".as_ref(), &bump];
+        let signer = &[seeds];
+
+        let cpi_accounts = swap_escrow::cpi::accounts::Deposit {
+            depositor: ctx.accounts.depositor.to_account_info(),
+            payer: ctx.accounts.payer.to_account_info(),
+            escrow_account: ctx.accounts.escrow_account.to_account_info(),
+            config: ctx.accounts.config.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            metadata_account: None,
+            token_account: ctx.accounts.token_account.to_account_info(),
+            vault_account: ctx.accounts.vault_account.to_account_info(),
+            token_program: ctx.accounts.token_program.to_account_info(),
+            associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.swap_escrow_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        );
+
+        swap_escrow::cpi::deposit(cpi_ctx, is_initializer, nft_index)
+    }
+
+    /// Forwards a `complete` signed by the collecting party's wallet
+    pub fn complete<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompleteViaCpi<'info>>,
+        is_initializer: bool,
+        nft_index: u8,
+    ) -> Result<()> {
+        let cpi_accounts = swap_escrow::cpi::accounts::Complete {
+            caller: ctx.accounts.caller.to_account_info(),
+            escrow_account: ctx.accounts.escrow_account.to_account_info(),
+            config: ctx.accounts.config.to_account_info(),
+            vault_funder: ctx.accounts.vault_funder.to_account_info(),
+            recipient: ctx.accounts.caller.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            metadata_account: None,
+            vault_account: ctx.accounts.vault_account.to_account_info(),
+            recipient_token_account: ctx.accounts.recipient_token_account.to_account_info(),
+            delivery_wallet: None,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(ctx.accounts.swap_escrow_program.to_account_info(), cpi_accounts)
+            .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+
+        swap_escrow::cpi::complete(cpi_ctx, is_initializer, nft_index)
+    }
+
+    /// Settles an escrow with this program's `settler` PDA as the caller
+    pub fn settle<'info>(ctx: Context<'_, '_, '_, 'info, SettleViaCpi<'info>>) -> Result<()> {
+        let bump = [ctx.bumps.settler];
+        let seeds: &[&[u8]] = &[b"settler".as_ref(), &bump];
+        let signer = &[seeds];
+
+        let cpi_accounts = swap_escrow::cpi::accounts::Settle {
+            caller: ctx.accounts.settler.to_account_info(),
+            escrow_account: ctx.accounts.escrow_account.to_account_info(),
+            config: ctx.accounts.config.to_account_info(),
+            initializer: ctx.accounts.initializer.to_account_info(),
+            taker: ctx.accounts.taker.to_account_info(),
+            rent_payer: ctx.accounts.rent_payer.to_account_info(),
+            initializer_stats: ctx.accounts.initializer_stats.as_ref().map(|a| a.to_account_info()),
+            initializer_escrow_list: None,
+            taker_escrow_list: None,
+            fee_recipient: ctx.accounts.fee_recipient.to_account_info(),
+            token_leg_mint: None,
+            token_leg_vault: None,
+            token_leg_destination: None,
+            token_leg_vault_funder: None,
+            token_program: ctx.accounts.token_program.to_account_info(),
+            associated_token_program: ctx.accounts.associated_token_program.to_account_info(),
+            system_program: ctx.accounts.system_program.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            ctx.accounts.swap_escrow_program.to_account_info(),
+            cpi_accounts,
+            signer,
+        )
+        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+
+        swap_escrow::cpi::settle(cpi_ctx)
+    }
+}
+
+#[derive(Accounts)]
+pub struct DepositViaCpi<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: PDA of this program that holds the NFT and is the escrow party
+    #[account(seeds = [b"depositor".as_ref()], bump)]
+    pub depositor: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub escrow_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub config: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub mint: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub token_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub vault_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub token_program: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub associated_token_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    pub swap_escrow_program: Program<'info, SwapEscrow>,
+}
+
+#[derive(Accounts)]
+pub struct CompleteViaCpi<'info> {
+    /// The collecting party; its signature carries through to swap_escrow
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub escrow_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub config: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub vault_funder: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub mint: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub vault_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub recipient_token_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub token_program: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub associated_token_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    pub swap_escrow_program: Program<'info, SwapEscrow>,
+}
+
+#[derive(Accounts)]
+pub struct SettleViaCpi<'info> {
+    /// CHECK: PDA of this program that settles on behalf of an aggregator
+    #[account(seeds = [b"settler".as_ref()], bump)]
+    pub settler: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub escrow_account: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub config: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub initializer: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub taker: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub initializer_stats: Option<UncheckedAccount<'info>>,
+    /// CHECK: Validated by swap_escrow
+    #[account(mut)]
+    pub fee_recipient: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub token_program: UncheckedAccount<'info>,
+    /// CHECK: Validated by swap_escrow
+    pub associated_token_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+    pub swap_escrow_program: Program<'info, SwapEscrow>,
+}
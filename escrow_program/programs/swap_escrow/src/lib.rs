@@ -1,62 +1,562 @@
 use anchor_lang::prelude::*;
-use anchor_spl::associated_token::AssociatedToken;
-use anchor_spl::token::{self, Mint, Token, TokenAccount};
+use anchor_lang::system_program;
+use anchor_spl::associated_token::{self, AssociatedToken};
+use anchor_spl::metadata::MetadataAccount;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface};
 use solana_program::clock::Clock;
+use solana_program::instruction::Instruction;
+use solana_program::program::invoke_signed;
+use solana_program::program_option::COption;
 use solana_program::pubkey::Pubkey;
 
 declare_id!("Fup37jJN7tFaBmdwNegtCHd8Z8ruuiSL5dt3hpEfJWEW");
 
+pub const DEFAULT_TIMEOUT_SECONDS: i64 = 86400; // 24 hours
+pub const MIN_TIMEOUT_SECONDS: i64 = 300; // 5 minutes
+pub const MAX_TIMEOUT_SECONDS: i64 = 30 * 86400; // 30 days
+pub const MAX_NFTS_PER_SIDE: u8 = 20;
+pub const MAX_FEE_BASIS_POINTS: u16 = 1000; // 10%
+pub const MAX_TIMEOUT_EXTENSION_SECONDS: i64 = 30 * 86400; // 30 days total
+pub const MAX_ALLOWED_TAKER_COLLECTIONS: usize = 5;
+/// Metaplex caps symbols at 10 bytes; shorter expected symbols are zero-padded.
+pub const MAX_SYMBOL_LENGTH: usize = 10;
+/// Layout version written to new escrow accounts. Version 1 is the first shipped layout and
+/// covers every field added to `EscrowAccount` before release; accounts from before versioning
+/// count as 0. Any change to the layout after release must bump this and teach `migrate` to
+/// read the previous version.
+pub const ESCROW_ACCOUNT_VERSION: u8 = 1;
+/// Upper bound on what a keeper can be paid out of an escrow's rent for reaping it.
+pub const MAX_CRANK_FEE_LAMPORTS: u64 = 1_000_000; // 0.001 SOL
+/// Most open escrows a single `UserEscrowList` can track.
+pub const MAX_LISTED_ESCROWS: usize = 64;
+// Most token programs the config allowlist can hold
+pub const MAX_ALLOWED_TOKEN_PROGRAMS: usize = 4;
+/// Largest slice `deposit_all` and `complete_all` should be asked to process per transaction.
+/// Each NFT adds three or four accounts and up to about 40k compute units (creating an ATA
+/// plus the transfer), so four fit in the transaction size limit and a 200k compute budget
+/// without address lookup tables. Larger bundles are worked through in several slices.
+pub const SAFE_CHUNK_SIZE: u8 = 4;
+/// Size of a Token-2022 associated token account: the 165-byte base account, the account
+/// type byte and the 4-byte header of the ImmutableOwner extension every such ATA carries.
+pub const TOKEN_2022_ATA_LEN: usize = 170;
+/// Programs behind compressed NFTs
+pub const BUBBLEGUM_PROGRAM_ID: Pubkey = solana_program::pubkey!("BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY");
+pub const SPL_ACCOUNT_COMPRESSION_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK");
+pub const SPL_NOOP_PROGRAM_ID: Pubkey = solana_program::pubkey!("noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV");
+/// Anchor discriminator of Bubblegum's `transfer` instruction
+const BUBBLEGUM_TRANSFER_DISCRIMINATOR: [u8; 8] = [163, 52, 200, 231, 140, 3, 69, 186];
+
 #[program]
 pub mod swap_escrow {
     use super::*;
 
+    pub fn initialize_config(ctx: Context<InitializeConfig>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.pauser = ctx.accounts.admin.key();
+        config.paused = false;
+        config.crank_fee_lamports = 0;
+        config.max_active_escrows = 0;
+        config.allowed_token_programs = vec![anchor_spl::token::ID, anchor_spl::token_2022::ID];
+        config.failure_reporter = ctx.accounts.admin.key();
+        config.failure_threshold = 0;
+        config.failure_window_seconds = 0;
+        config.failure_window_start = 0;
+        config.failure_count = 0;
+        config.bump = ctx.bumps.config;
+
+        msg!("Program config initialized with admin {}", config.admin);
+
+        Ok(())
+    }
+
+    /// Pauses or unpauses the program. Either the admin or the pauser may do this, so the
+    /// pause switch can sit with an ops key while the admin stays locked away.
+    pub fn set_paused(ctx: Context<SetPaused>, paused: bool) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let authority = ctx.accounts.authority.key();
+        require!(authority == config.admin || authority == config.pauser, EscrowError::NotPauser);
+
+        config.paused = paused;
+        // Unpausing starts the circuit breaker's count afresh
+        if !paused {
+            config.failure_count = 0;
+            config.failure_window_start = 0;
+        }
+
+        msg!("Program {} by {}", if paused { "paused" } else { "unpaused" }, authority);
+
+        Ok(())
+    }
+
+    pub fn set_admin(ctx: Context<SetAdmin>, new_admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_admin = config.admin;
+        config.admin = new_admin;
+
+        msg!("Config admin changed from {} to {}", previous_admin, new_admin);
+
+        Ok(())
+    }
+
+    pub fn set_pauser(ctx: Context<SetPauser>, new_pauser: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.pauser = new_pauser;
+
+        msg!("Config pauser set to {}", new_pauser);
+
+        Ok(())
+    }
+
+    pub fn set_crank_fee(ctx: Context<SetCrankFee>, crank_fee_lamports: u64) -> Result<()> {
+        require!(crank_fee_lamports <= MAX_CRANK_FEE_LAMPORTS, EscrowError::FeeTooHigh);
+
+        let config = &mut ctx.accounts.config;
+        config.crank_fee_lamports = crank_fee_lamports;
+
+        msg!("Crank fee set to {} lamports", crank_fee_lamports);
+
+        Ok(())
+    }
+
+    pub fn set_max_active_escrows(
+        ctx: Context<SetMaxActiveEscrows>,
+        max_active_escrows: u16,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.max_active_escrows = max_active_escrows;
+
+        msg!("Active escrows per initializer capped at {}", max_active_escrows);
+
+        Ok(())
+    }
+
+    /// Replaces the token programs new escrows may be created with. Escrows already open keep
+    /// the program they were created with.
+    pub fn set_allowed_token_programs(
+        ctx: Context<SetAllowedTokenPrograms>,
+        allowed_token_programs: Vec<Pubkey>,
+    ) -> Result<()> {
+        require!(
+            allowed_token_programs.len() <= MAX_ALLOWED_TOKEN_PROGRAMS,
+            EscrowError::TooManyTokenPrograms
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.allowed_token_programs = allowed_token_programs;
+
+        msg!("Allowed token programs set to {:?}", config.allowed_token_programs);
+
+        Ok(())
+    }
+
+    /// Configures the circuit breaker: once `failure_threshold` completion failures are
+    /// reported within `failure_window_seconds`, the program pauses itself. A threshold of 0
+    /// turns it off.
+    pub fn set_circuit_breaker(
+        ctx: Context<SetCircuitBreaker>,
+        failure_reporter: Pubkey,
+        failure_threshold: u16,
+        failure_window_seconds: i64,
+    ) -> Result<()> {
+        require!(
+            failure_threshold == 0 || failure_window_seconds > 0,
+            EscrowError::InvalidFailureWindow
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.failure_reporter = failure_reporter;
+        config.failure_threshold = failure_threshold;
+        config.failure_window_seconds = failure_window_seconds;
+        config.failure_window_start = 0;
+        config.failure_count = 0;
+
+        msg!(
+            "Circuit breaker set to {} failures per {} seconds, reported by {}",
+            failure_threshold,
+            failure_window_seconds,
+            failure_reporter
+        );
+
+        Ok(())
+    }
+
+    /// Records a completion that failed off-chain monitoring saw. A failed transaction rolls
+    /// back everything it wrote, so failures can only be counted by reporting them here.
+    pub fn report_completion_failure(ctx: Context<ReportCompletionFailure>, escrow: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let now = Clock::get()?.unix_timestamp;
+
+        if now.saturating_sub(config.failure_window_start) >= config.failure_window_seconds {
+            config.failure_window_start = now;
+            config.failure_count = 0;
+        }
+        config.failure_count = config.failure_count.saturating_add(1);
+
+        msg!("Completion failure reported for escrow {} ({} in window)", escrow, config.failure_count);
+
+        if config.failure_threshold > 0 && config.failure_count >= config.failure_threshold && !config.paused {
+            config.paused = true;
+
+            emit!(CircuitBreakerTripped {
+                failure_count: config.failure_count,
+                window_start: config.failure_window_start,
+                tripped_at: now,
+            });
+            msg!("Circuit breaker tripped; program paused");
+        }
+
+        Ok(())
+    }
+
+    /// Creates the caller's escrow list. Once it exists, escrows the caller takes part in can be
+    /// added to it at initialization and are removed again when they close.
+    pub fn create_escrow_list(ctx: Context<CreateEscrowList>) -> Result<()> {
+        let list = &mut ctx.accounts.escrow_list;
+        list.owner = ctx.accounts.owner.key();
+        list.bump = ctx.bumps.escrow_list;
+        list.escrows = Vec::new();
+
+        msg!("Escrow list created for {}", list.owner);
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         ctx: Context<Initialize>,
+        nonce: u64,
         initializer_nft_count: u8,
         taker_nft_count: u8,
-        escrow_bump: u8,
+        timeout_in_seconds: i64,
+        sol_amount: u64,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        fee_recipient: Pubkey,
+        initializer_collection: Option<Pubkey>,
+        taker_collection: Option<Pubkey>,
+        initializer_delegate: Option<Pubkey>,
+        taker_delegate: Option<Pubkey>,
+        allowed_taker_collections: Vec<Pubkey>,
+        token_mint: Pubkey,
+        token_amount: u64,
+        initializer_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+        taker_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+        deposit_deadline: i64,
+        arbiter: Option<Pubkey>,
+        sol_deposit_deadline: i64,
+        enforce_royalties: bool,
+        sliding_timeout: bool,
+        block_on_metadata_change: bool,
+        keeper_grace_seconds: i64,
+        memo: [u8; 32],
+        approver: Option<Pubkey>,
+        nft_amounts: Vec<u64>,
+        initializer_delivery_address: Option<Pubkey>,
+        taker_delivery_address: Option<Pubkey>,
     ) -> Result<()> {
-        // Validate NFT counts (1-3 NFTs per participant)
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+        ctx.accounts.config.check_token_program(&ctx.accounts.token_program.key())?;
+
+        // The initializer cannot trade with themselves
         require!(
-            initializer_nft_count > 0 && initializer_nft_count <= 3,
-            EscrowError::InvalidNftCount
+            ctx.accounts.initializer.key() != ctx.accounts.taker.key(),
+            EscrowError::SelfEscrowNotAllowed
         );
-        require!(
-            taker_nft_count > 0 && taker_nft_count <= 3,
-            EscrowError::InvalidNftCount
+
+        setup_escrow(
+            &mut ctx.accounts.escrow_account,
+            ctx.accounts.initializer.key(),
+            ctx.accounts.taker.key(),
+            ctx.remaining_accounts,
+            initializer_nft_count,
+            taker_nft_count,
+            ctx.bumps.escrow_account,
+            timeout_in_seconds,
+            sol_amount,
+            maker_fee_bps,
+            taker_fee_bps,
+            fee_recipient,
+            initializer_collection,
+            taker_collection,
+            initializer_delegate,
+            taker_delegate,
+            allowed_taker_collections,
+            token_mint,
+            token_amount,
+            initializer_symbol,
+            taker_symbol,
+            deposit_deadline,
+            arbiter,
+            sol_deposit_deadline,
+            enforce_royalties,
+            sliding_timeout,
+            block_on_metadata_change,
+            keeper_grace_seconds,
+            memo,
+            approver,
+            nft_amounts,
+            initializer_delivery_address,
+            taker_delivery_address,
+        )?;
+
+        // The nonce lets the same pair run several escrows side by side
+        ctx.accounts.escrow_account.nonce = nonce;
+        ctx.accounts.escrow_account.rent_payer = ctx.accounts.payer.key();
+        ctx.accounts.escrow_account.token_program_id = ctx.accounts.token_program.key();
+
+        open_active_slot(
+            &mut ctx.accounts.initializer_stats,
+            ctx.accounts.initializer.key(),
+            ctx.bumps.initializer_stats,
+            ctx.accounts.config.max_active_escrows,
+        )?;
+        ctx.accounts.escrow_account.counted_as_active = true;
+
+        // Only a list whose owner signs can be written to, or anyone could fill a stranger's
+        let escrow_key = ctx.accounts.escrow_account.key();
+        if let Some(list) = ctx.accounts.initializer_escrow_list.as_mut() {
+            list_escrow(
+                list,
+                escrow_key,
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            )?;
+            ctx.accounts.escrow_account.listed_initializer = true;
+        }
+        if let Some(list) = ctx.accounts.taker_escrow_list.as_mut() {
+            require!(ctx.accounts.taker.is_signer, EscrowError::EscrowListOwnerMustSign);
+            list_escrow(
+                list,
+                escrow_key,
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            )?;
+            ctx.accounts.escrow_account.listed_taker = true;
+        }
+
+        emit!(EscrowInitialized {
+            escrow: ctx.accounts.escrow_account.key(),
+            initializer: ctx.accounts.escrow_account.initializer,
+            taker: ctx.accounts.escrow_account.taker,
+            nonce,
+            memo,
+            created_at: ctx.accounts.escrow_account.created_at,
+        });
+
+        msg!(
+            "Escrow initialized between {} and {}",
+            ctx.accounts.initializer.key(),
+            ctx.accounts.taker.key()
         );
 
-        // Initialize the escrow account
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_open(
+        ctx: Context<InitializeOpen>,
+        nonce: u64,
+        initializer_nft_count: u8,
+        taker_nft_count: u8,
+        timeout_in_seconds: i64,
+        sol_amount: u64,
+        maker_fee_bps: u16,
+        taker_fee_bps: u16,
+        fee_recipient: Pubkey,
+        initializer_collection: Option<Pubkey>,
+        taker_collection: Option<Pubkey>,
+        initializer_delegate: Option<Pubkey>,
+        allowed_taker_collections: Vec<Pubkey>,
+        token_mint: Pubkey,
+        token_amount: u64,
+        initializer_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+        taker_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+        deposit_deadline: i64,
+        arbiter: Option<Pubkey>,
+        sol_deposit_deadline: i64,
+        enforce_royalties: bool,
+        sliding_timeout: bool,
+        block_on_metadata_change: bool,
+        keeper_grace_seconds: i64,
+        memo: [u8; 32],
+        approver: Option<Pubkey>,
+        nft_amounts: Vec<u64>,
+        initializer_delivery_address: Option<Pubkey>,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+        ctx.accounts.config.check_token_program(&ctx.accounts.token_program.key())?;
+
+        // The taker is left unset until someone claims the offer, so only the
+        // initializer can register a delegate up front
+        setup_escrow(
+            &mut ctx.accounts.escrow_account,
+            ctx.accounts.initializer.key(),
+            Pubkey::default(),
+            ctx.remaining_accounts,
+            initializer_nft_count,
+            taker_nft_count,
+            ctx.bumps.escrow_account,
+            timeout_in_seconds,
+            sol_amount,
+            maker_fee_bps,
+            taker_fee_bps,
+            fee_recipient,
+            initializer_collection,
+            taker_collection,
+            initializer_delegate,
+            None,
+            allowed_taker_collections,
+            token_mint,
+            token_amount,
+            initializer_symbol,
+            taker_symbol,
+            deposit_deadline,
+            arbiter,
+            sol_deposit_deadline,
+            enforce_royalties,
+            sliding_timeout,
+            block_on_metadata_change,
+            keeper_grace_seconds,
+            memo,
+            approver,
+            nft_amounts,
+            initializer_delivery_address,
+            None,
+        )?;
+
         let escrow = &mut ctx.accounts.escrow_account;
-        escrow.initializer = ctx.accounts.initializer.key();
-        escrow.taker = ctx.accounts.taker.key();
-        escrow.initializer_nft_count = initializer_nft_count;
-        escrow.taker_nft_count = taker_nft_count;
-        escrow.is_initialized = true;
-        escrow.initializer_deposited = false;
-        escrow.taker_deposited = false;
-        escrow.bump = escrow_bump;
-        escrow.created_at = Clock::get()?.unix_timestamp;
-        escrow.timeout_in_seconds = 86400; // Default 24 hour timeout
+        escrow.nonce = nonce;
+        escrow.is_open_offer = true;
+        escrow.rent_payer = ctx.accounts.payer.key();
+        escrow.token_program_id = ctx.accounts.token_program.key();
 
-        // Store the mint addresses for initializer's NFTs
-        for i in 0..initializer_nft_count as usize {
-            escrow.initializer_nft_mints[i] = ctx.remaining_accounts[i].key();
-        }
+        open_active_slot(
+            &mut ctx.accounts.initializer_stats,
+            ctx.accounts.initializer.key(),
+            ctx.bumps.initializer_stats,
+            ctx.accounts.config.max_active_escrows,
+        )?;
+        ctx.accounts.escrow_account.counted_as_active = true;
 
-        // Store the mint addresses for taker's NFTs
-        for i in 0..taker_nft_count as usize {
-            escrow.taker_nft_mints[i] =
-                ctx.remaining_accounts[initializer_nft_count as usize + i].key();
+        // The taker isn't known yet, so only the initializer's list can track an open offer
+        let escrow_key = ctx.accounts.escrow_account.key();
+        if let Some(list) = ctx.accounts.initializer_escrow_list.as_mut() {
+            list_escrow(
+                list,
+                escrow_key,
+                ctx.accounts.payer.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            )?;
+            ctx.accounts.escrow_account.listed_initializer = true;
         }
 
+        emit!(EscrowInitialized {
+            escrow: ctx.accounts.escrow_account.key(),
+            initializer: ctx.accounts.escrow_account.initializer,
+            taker: ctx.accounts.escrow_account.taker,
+            nonce,
+            memo,
+            created_at: ctx.accounts.escrow_account.created_at,
+        });
+
         msg!(
-            "Escrow initialized between {} and {}",
-            escrow.initializer,
-            escrow.taker
+            "Open offer {} initialized by {}",
+            nonce,
+            ctx.accounts.escrow_account.initializer
+        );
+
+        Ok(())
+    }
+
+    pub fn claim_offer<'info>(ctx: Context<'_, '_, '_, 'info, ClaimOffer<'info>>) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.is_open_offer, EscrowError::NotAnOpenOffer);
+        // Every transfer for this escrow must go through the token program it was created with
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+        require!(escrow.approved, EscrowError::EscrowNotApproved);
+        require!(
+            escrow.deposit_window_open(Clock::get()?.unix_timestamp),
+            EscrowError::DepositWindowClosed
+        );
+        // The first taker to claim wins; later claims see a taker already set
+        require!(escrow.taker == Pubkey::default(), EscrowError::OfferAlreadyClaimed);
+        require!(
+            ctx.accounts.taker.key() != escrow.initializer,
+            EscrowError::SelfEscrowNotAllowed
         );
-        msg!("Initializer will provide {} NFTs", initializer_nft_count);
-        msg!("Taker will provide {} NFTs", taker_nft_count);
+
+        // Metadata is fingerprinted NFT by NFT, which claiming in one go cannot do
+        require!(!escrow.block_on_metadata_change, EscrowError::MissingMetadataAccount);
+
+        escrow.taker = ctx.accounts.taker.key();
+
+        // The claimer becomes the taker by depositing every counter-asset in this instruction
+        let pending = escrow.pending_deposits(false);
+        let deposited_mints = deposit_pending_nfts(
+            &ctx.accounts.escrow_account,
+            false,
+            &pending,
+            ctx.accounts.taker.to_account_info(),
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        let sol_amount = ctx.accounts.escrow_account.sol_amount;
+        if sol_amount > 0 {
+            require!(
+                ctx.accounts.escrow_account.sol_quote_valid(Clock::get()?.unix_timestamp),
+                EscrowError::SolQuoteExpired
+            );
+            let sol_due = ctx.accounts.escrow_account.taker_sol_due();
+            require!(
+                ctx.accounts.taker.lamports() >= sol_due,
+                EscrowError::InsufficientSolDeposit
+            );
+
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.taker.to_account_info(),
+                to: ctx.accounts.escrow_account.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.system_program.to_account_info();
+            system_program::transfer(CpiContext::new(cpi_program, cpi_accounts), sol_due)?;
+
+            msg!("Taker deposited {} lamports into escrow", sol_due);
+        }
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        let deposited_at = Clock::get()?.unix_timestamp;
+        escrow.last_activity_at = deposited_at;
+        for (&nft_index, &mint) in pending.iter().zip(deposited_mints.iter()) {
+            escrow.record_vault_funder(false, nft_index, ctx.accounts.taker.key());
+            escrow.record_deposit_time(false, nft_index, deposited_at);
+            escrow.taker_nft_mints[nft_index as usize] = mint;
+            escrow.taker_nft_deposited[nft_index as usize] = true;
+
+            emit!(NftDeposited {
+                escrow: escrow.key(),
+                depositor: ctx.accounts.taker.key(),
+                mint,
+                is_initializer: false,
+                nft_index,
+                deposited_at,
+            });
+        }
+        escrow.sol_deposited = sol_amount > 0;
+        // Any token leg is deposited separately with deposit_tokens
+        escrow.taker_deposited = escrow.taker_side_complete();
+        escrow.refresh_funding_status();
+        escrow.emit_deposit_progress(escrow.key());
+
+        msg!("Open offer claimed by {}", escrow.taker);
 
         Ok(())
     }
@@ -66,222 +566,875 @@ pub mod swap_escrow {
         is_initializer: bool,
         nft_index: u8,
     ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        deposit_nft(
+            &mut ctx.accounts.escrow_account,
+            is_initializer,
+            nft_index,
+            &ctx.accounts.depositor,
+            ctx.accounts.payer.key(),
+            &ctx.accounts.mint,
+            ctx.accounts.metadata_account.as_deref(),
+            &ctx.accounts.token_account,
+            &ctx.accounts.vault_account,
+            &ctx.accounts.token_program,
+        )
+    }
+
+    /// `deposit` into a vault the depositor already created, skipping the vault creation
+    /// path. The vault must be the escrow's associated token account for the mint; its rent
+    /// goes back to the depositor when it closes.
+    pub fn deposit_to_vault(
+        ctx: Context<DepositToVault>,
+        is_initializer: bool,
+        nft_index: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        deposit_nft(
+            &mut ctx.accounts.escrow_account,
+            is_initializer,
+            nft_index,
+            &ctx.accounts.depositor,
+            ctx.accounts.depositor.key(),
+            &ctx.accounts.mint,
+            ctx.accounts.metadata_account.as_deref(),
+            &ctx.accounts.token_account,
+            &ctx.accounts.vault_account,
+            &ctx.accounts.token_program,
+        )
+    }
+
+    /// Deposits the compressed NFT at `nft_index`. Its slot lists the cNFT's asset id where an
+    /// SPL NFT would list its mint. The leaf's proof path follows in `remaining_accounts`, and
+    /// Bubblegum checks it while moving the leaf to the escrow. There is no vault, so nothing
+    /// needs paying for.
+    pub fn deposit_compressed<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositCompressed<'info>>,
+        is_initializer: bool,
+        nft_index: u8,
+        leaf: CompressedLeaf,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &ctx.accounts.escrow_account;
+        let now = Clock::get()?.unix_timestamp;
+
+        // No token program is involved, so the escrow's own one trivially matches
+        check_deposit_allowed(
+            escrow,
+            is_initializer,
+            &ctx.accounts.depositor.key(),
+            &escrow.token_program_id,
+            now,
+        )?;
+
+        let (nft_mints, nft_deposited) = if is_initializer {
+            (&escrow.initializer_nft_mints, &escrow.initializer_nft_deposited)
+        } else {
+            (&escrow.taker_nft_mints, &escrow.taker_nft_deposited)
+        };
+        require!((nft_index as usize) < nft_mints.len(), EscrowError::InvalidNftIndex);
+        require!(!nft_deposited[nft_index as usize], EscrowError::NftAlreadyDeposited);
+        // Whitelisted slots and metadata checks both work off a token mint and its metadata
+        // account, which a compressed NFT doesn't have
+        require!(!escrow.is_whitelist_slot(is_initializer, nft_index), EscrowError::InvalidNftMint);
+        require!(
+            !escrow.requires_metadata(is_initializer) && !escrow.block_on_metadata_change,
+            EscrowError::MissingMetadataAccount
+        );
+
+        let asset_id = compressed_asset_id(&ctx.accounts.merkle_tree.key(), leaf.nonce);
+        require!(nft_mints[nft_index as usize] == asset_id, EscrowError::InvalidNftMint);
+
+        // The party owns the leaf; the signer is either the party or the leaf's delegate
+        let depositor = ctx.accounts.depositor.key();
+        require!(
+            depositor == ctx.accounts.leaf_owner.key() || depositor == ctx.accounts.leaf_delegate.key(),
+            EscrowError::InvalidDepositor
+        );
+
+        transfer_compressed(
+            escrow,
+            ctx.accounts.tree_config.to_account_info(),
+            ctx.accounts.leaf_owner.to_account_info(),
+            ctx.accounts.leaf_delegate.to_account_info(),
+            escrow.to_account_info(),
+            ctx.accounts.merkle_tree.to_account_info(),
+            ctx.accounts.log_wrapper.to_account_info(),
+            ctx.accounts.compression_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.bubblegum_program.to_account_info(),
+            ctx.remaining_accounts,
+            &leaf,
+        )?;
+
+        msg!("Transferred compressed NFT {} to escrow", asset_id);
+
         let escrow = &mut ctx.accounts.escrow_account;
-        
-        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
-        
-        // Verify the depositor is correct
+        escrow.last_activity_at = now;
+        escrow.record_deposit_time(is_initializer, nft_index, now);
+        escrow.record_compressed(is_initializer, nft_index, true);
+        emit!(NftDeposited {
+            escrow: escrow.key(),
+            depositor,
+            mint: asset_id,
+            is_initializer,
+            nft_index,
+            deposited_at: now,
+        });
+
         if is_initializer {
-            require!(ctx.accounts.depositor.key() == escrow.initializer, EscrowError::InvalidDepositor);
-            require!(!escrow.initializer_deposited, EscrowError::AlreadyDeposited);
-            require!(nft_index < escrow.initializer_nft_count, EscrowError::InvalidNftIndex);
+            escrow.initializer_nft_deposited[nft_index as usize] = true;
+            escrow.initializer_deposited = escrow.initializer_nft_deposited.iter().all(|&d| d);
         } else {
-            require!(ctx.accounts.depositor.key() == escrow.taker, EscrowError::InvalidDepositor);
-            require!(!escrow.taker_deposited, EscrowError::AlreadyDeposited);
-            require!(nft_index < escrow.taker_nft_count, EscrowError::InvalidNftIndex);
+            escrow.taker_nft_deposited[nft_index as usize] = true;
+            // The taker's side is only complete once any SOL or token payment has landed too
+            escrow.taker_deposited = escrow.taker_side_complete();
         }
-        
-        // Get the expected mint for this NFT index
-        let expected_mint = if is_initializer {
-            escrow.initializer_nft_mints[nft_index as usize]
+        escrow.refresh_funding_status();
+        escrow.emit_deposit_progress(escrow.key());
+
+        Ok(())
+    }
+
+    /// Returns a deposited compressed NFT to its depositor. Allowed whenever `withdraw_deposited`
+    /// would be, and also once the escrow has timed out without any collection, since the
+    /// vault-based `cancel` and `refund_expired` paths can't move compressed NFTs.
+    pub fn withdraw_compressed<'info>(
+        ctx: Context<'_, '_, '_, 'info, WithdrawCompressed<'info>>,
+        is_initializer: bool,
+        nft_index: u8,
+        leaf: CompressedLeaf,
+    ) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(!escrow.collection_started(), EscrowError::CannotDepositDuringSettlement);
+
+        let (nft_count, counterparty_deposited) = if is_initializer {
+            (escrow.initializer_nft_count, escrow.taker_deposited)
         } else {
-            escrow.taker_nft_mints[nft_index as usize]
+            (escrow.taker_nft_count, escrow.initializer_deposited)
         };
+        require!(nft_index < nft_count, EscrowError::InvalidNftIndex);
+        let expired = Clock::get()?.unix_timestamp > escrow.expires_at()?;
+        require!(
+            expired || (escrow.is_accepting_deposits() && !counterparty_deposited),
+            EscrowError::CounterpartyAlreadyDeposited
+        );
 
-        
-        let token_account = &ctx.accounts.token_account;
-        require!(token_account.owner == ctx.accounts.depositor.key(), EscrowError::InvalidTokenAccount);
-        require!(token_account.mint == expected_mint, EscrowError::InvalidNftMint);
-        require!(token_account.amount == 1, EscrowError::InvalidTokenAmount);
-        
-        // Verify vault account
-        let vault_account = &ctx.accounts.vault_account;
-        require!(vault_account.mint == expected_mint, EscrowError::InvalidNftMint);
-        
-        // Check if this NFT has already been deposited
-        if is_initializer {
-            require!(!escrow.initializer_nft_deposited[nft_index as usize], EscrowError::NftAlreadyDeposited);
+        let (asset_id, deposited, compressed) = if is_initializer {
+            (
+                escrow.initializer_nft_mints[nft_index as usize],
+                escrow.initializer_nft_deposited[nft_index as usize],
+                escrow.initializer_nft_compressed[nft_index as usize],
+            )
         } else {
-            require!(!escrow.taker_nft_deposited[nft_index as usize], EscrowError::NftAlreadyDeposited);
-        }
-        
-        // Transfer the NFT to the vault
-        let cpi_accounts = token::Transfer {
-            from: ctx.accounts.token_account.to_account_info(),
-            to: ctx.accounts.vault_account.to_account_info(),
-            authority: ctx.accounts.depositor.to_account_info(),
+            (
+                escrow.taker_nft_mints[nft_index as usize],
+                escrow.taker_nft_deposited[nft_index as usize],
+                escrow.taker_nft_compressed[nft_index as usize],
+            )
         };
-        
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        
-        token::transfer(cpi_ctx, 1)?;
-        
-        msg!("Transferred NFT {} to escrow vault", expected_mint);
-        
-        // Mark this NFT as deposited
+        require!(deposited, EscrowError::NftNotDeposited);
+        require!(compressed, EscrowError::NotCompressedNft);
+        require!(
+            compressed_asset_id(&ctx.accounts.merkle_tree.key(), leaf.nonce) == asset_id,
+            EscrowError::InvalidNftMint
+        );
+
+        // The escrow owns the leaf and, as its new owner, is also its delegate
+        transfer_compressed(
+            escrow,
+            ctx.accounts.tree_config.to_account_info(),
+            escrow.to_account_info(),
+            escrow.to_account_info(),
+            ctx.accounts.depositor.to_account_info(),
+            ctx.accounts.merkle_tree.to_account_info(),
+            ctx.accounts.log_wrapper.to_account_info(),
+            ctx.accounts.compression_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.bubblegum_program.to_account_info(),
+            ctx.remaining_accounts,
+            &leaf,
+        )?;
+
+        msg!("Returned compressed NFT {} to depositor", asset_id);
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        escrow.record_deposit_time(is_initializer, nft_index, 0);
+        escrow.record_compressed(is_initializer, nft_index, false);
         if is_initializer {
-            escrow.initializer_nft_deposited[nft_index as usize] = true;
-            
-            // Check if all initializer NFTs have been deposited
-            let mut all_deposited = true;
-            for i in 0..escrow.initializer_nft_count as usize {
-                if !escrow.initializer_nft_deposited[i] {
-                    all_deposited = false;
-                    break;
-                }
-            }
-            
-            if all_deposited {
-                escrow.initializer_deposited = true;
-                msg!("Initializer has deposited all NFTs");
-            }
+            escrow.initializer_nft_deposited[nft_index as usize] = false;
+            escrow.initializer_deposited = false;
         } else {
-            escrow.taker_nft_deposited[nft_index as usize] = true;
-            
-            // Check if all taker NFTs have been deposited
-            let mut all_deposited = true;
-            for i in 0..escrow.taker_nft_count as usize {
-                if !escrow.taker_nft_deposited[i] {
-                    all_deposited = false;
-                    break;
-                }
-            }
-            
-            if all_deposited {
-                escrow.taker_deposited = true;
-                msg!("Taker has deposited all NFTs");
-            }
+            escrow.taker_nft_deposited[nft_index as usize] = false;
+            escrow.taker_deposited = false;
         }
-        
-        // Check if both parties have deposited all their NFTs
-        if escrow.initializer_deposited && escrow.taker_deposited {
-            msg!("All NFTs have been deposited. Escrow is ready for completion.");
+        // An escrow already being unwound keeps its status
+        if escrow.is_accepting_deposits() || escrow.status == EscrowStatus::Funded {
+            escrow.refresh_funding_status();
         }
-        
+        escrow.emit_deposit_progress(escrow.key());
+
         Ok(())
     }
 
-    pub fn complete(
-        ctx: Context<Complete>,
+    /// `complete` for a compressed NFT: moves the leaf at `nft_index` from the escrow to the
+    /// caller. The leaf's proof path follows in `remaining_accounts`.
+    pub fn complete_compressed<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompleteCompressed<'info>>,
         is_initializer: bool,
         nft_index: u8,
+        leaf: CompressedLeaf,
     ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
         let escrow = &mut ctx.accounts.escrow_account;
-        
-        // Verify the escrow is initialized
+
         require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
-        
-        // Verify both parties have deposited all their NFTs
         require!(escrow.initializer_deposited && escrow.taker_deposited, EscrowError::DepositsIncomplete);
-        
-        // Verify the caller is either the initializer or the taker
+        require!(
+            escrow.status == EscrowStatus::Funded || escrow.status == EscrowStatus::Completing,
+            EscrowError::InvalidEscrowStatus
+        );
+        require!(escrow.initializer_ready && escrow.taker_ready, EscrowError::PartiesNotReady);
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
+
+        // Like `complete`, each party only collects the NFTs coming to them
         let is_initializer_caller = ctx.accounts.caller.key() == escrow.initializer;
-        require!(is_initializer_caller || ctx.accounts.caller.key() == escrow.taker, EscrowError::InvalidCaller);
-        
-        // Verify the NFT index is valid
-        if is_initializer {
-            require!(nft_index < escrow.taker_nft_count, EscrowError::InvalidNftIndex);
+        require!(is_initializer == is_initializer_caller, EscrowError::InvalidCaller);
+        // Royalties are paid out of each initializer NFT's metadata account, which a
+        // compressed NFT doesn't have
+        require!(is_initializer || !escrow.royalties_due(), EscrowError::RoyaltyAccountMissing);
+
+        let (nft_count, nft_mints, nft_collected, nft_compressed) = if is_initializer {
+            (
+                escrow.taker_nft_count,
+                &escrow.taker_nft_mints,
+                &escrow.taker_nft_collected,
+                &escrow.taker_nft_compressed,
+            )
         } else {
-            require!(nft_index < escrow.initializer_nft_count, EscrowError::InvalidNftIndex);
-        }
-        
-        // Get the expected mint for this NFT
-        let expected_mint = if is_initializer {
-            escrow.taker_nft_mints[nft_index as usize]
+            (
+                escrow.initializer_nft_count,
+                &escrow.initializer_nft_mints,
+                &escrow.initializer_nft_collected,
+                &escrow.initializer_nft_compressed,
+            )
+        };
+        require!(nft_index < nft_count, EscrowError::InvalidNftIndex);
+        require!(!nft_collected[nft_index as usize], EscrowError::NftAlreadyCollected);
+        require!(nft_compressed[nft_index as usize], EscrowError::NotCompressedNft);
+        let asset_id = nft_mints[nft_index as usize];
+        require!(
+            compressed_asset_id(&ctx.accounts.merkle_tree.key(), leaf.nonce) == asset_id,
+            EscrowError::InvalidNftMint
+        );
+
+        // The NFT goes to the caller's delivery address if it set one
+        let delivery_owner = escrow.delivery_owner(is_initializer);
+        let delivery_owner_info = if delivery_owner == ctx.accounts.caller.key() {
+            ctx.accounts.caller.to_account_info()
         } else {
-            escrow.initializer_nft_mints[nft_index as usize]
+            let wallet = ctx.accounts.delivery_wallet.as_ref().ok_or(EscrowError::InvalidRecipient)?;
+            require!(wallet.key() == delivery_owner, EscrowError::InvalidRecipient);
+            wallet.to_account_info()
         };
-        
-        // Verify the mint matches
-        require!(ctx.accounts.mint.key() == expected_mint, EscrowError::InvalidNftMint);
-        
-        // Verify the vault account is for the correct mint
-        require!(ctx.accounts.vault_account.mint == expected_mint, EscrowError::InvalidNftMint);
-        
-        // Verify the recipient account is for the correct mint
-        require!(ctx.accounts.recipient_token_account.mint == expected_mint, EscrowError::InvalidNftMint);
-        
-        // Check if this NFT has already been collected
+
+        // Mark the NFT collected and take the completion lock before the CPI, as `complete` does
         if is_initializer {
-            require!(!escrow.taker_nft_collected[nft_index as usize], EscrowError::NftAlreadyCollected);
+            escrow.taker_nft_collected[nft_index as usize] = true;
         } else {
-            require!(!escrow.initializer_nft_collected[nft_index as usize], EscrowError::NftAlreadyCollected);
+            escrow.initializer_nft_collected[nft_index as usize] = true;
         }
-        
-        // Determine the recipient based on which NFT is being collected
-        // Initializer NFTs go to taker, taker NFTs go to initializer
-        let recipient_expected_owner = if is_initializer {
-            escrow.initializer
+        escrow.in_progress = true;
+        escrow.exit(&crate::ID)?;
+
+        transfer_compressed(
+            escrow,
+            ctx.accounts.tree_config.to_account_info(),
+            escrow.to_account_info(),
+            escrow.to_account_info(),
+            delivery_owner_info,
+            ctx.accounts.merkle_tree.to_account_info(),
+            ctx.accounts.log_wrapper.to_account_info(),
+            ctx.accounts.compression_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            ctx.accounts.bubblegum_program.to_account_info(),
+            ctx.remaining_accounts,
+            &leaf,
+        )?;
+
+        msg!("Transferred compressed NFT {} from escrow to {}", asset_id, delivery_owner);
+
+        escrow.status = EscrowStatus::Completing;
+        escrow.last_collector = ctx.accounts.caller.key();
+        escrow.in_progress = false;
+
+        let collected_at = Clock::get()?.unix_timestamp;
+        emit!(NftCollected {
+            escrow: escrow.key(),
+            collector: ctx.accounts.caller.key(),
+            recipient: delivery_owner,
+            mint: asset_id,
+            nft_index,
+            collected_at,
+        });
+
+        if escrow.finish_collection() {
+            // Releasing the payments and closing the escrow is left to `finalize`
+            escrow.status = EscrowStatus::Completed;
+            msg!("All NFTs have been collected. The escrow is ready to be finalized.");
+        }
+
+        Ok(())
+    }
+
+    /// Dry-runs every `deposit` check for the NFT at `nft_index` without moving it or
+    /// touching the escrow, so a client can confirm the deposit would go through before
+    /// asking the user to sign
+    pub fn validate_deposit(
+        ctx: Context<ValidateDeposit>,
+        is_initializer: bool,
+        nft_index: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &ctx.accounts.escrow_account;
+
+        check_deposit_allowed(
+            escrow,
+            is_initializer,
+            ctx.accounts.depositor.key,
+            ctx.accounts.token_program.key,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        let expected_mint = validate_nft_deposit(
+            escrow,
+            is_initializer,
+            nft_index,
+            &ctx.accounts.mint,
+            &ctx.accounts.token_account,
+            ctx.accounts.metadata_account.as_deref(),
+            ctx.accounts.depositor.key,
+        )?;
+
+        msg!("NFT {} would be accepted at index {}", expected_mint, nft_index);
+
+        Ok(())
+    }
+
+    /// Deposits the pending NFTs among indices `start_index..start_index + count` on one side.
+    /// Large bundles are deposited a slice at a time; see `SAFE_CHUNK_SIZE`.
+    pub fn deposit_all<'info>(
+        ctx: Context<'_, '_, '_, 'info, DepositAll<'info>>,
+        is_initializer: bool,
+        start_index: u8,
+        count: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        // Once either side has started collecting, the escrow is settling and takes no more NFTs
+        require!(!escrow.collection_started(), EscrowError::CannotDepositDuringSettlement);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+        require!(
+            escrow.deposit_window_open(Clock::get()?.unix_timestamp),
+            EscrowError::DepositWindowClosed
+        );
+        // Deposits wait until the initializer has accepted any counter-offer
+        require!(!escrow.pending_acceptance, EscrowError::CounterPending);
+        require!(escrow.approved, EscrowError::EscrowNotApproved);
+
+        // Verify the depositor is the party itself or its registered delegate
+        escrow.check_depositor(is_initializer, &ctx.accounts.depositor.key())?;
+        // Every transfer for this escrow must go through the token program it was created with
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+        if is_initializer {
+            require!(!escrow.initializer_deposited, EscrowError::AlreadyDeposited);
         } else {
-            escrow.taker
+            require!(!escrow.taker_deposited, EscrowError::AlreadyDeposited);
+        }
+
+        // Metadata is fingerprinted NFT by NFT, so those NFTs go through `deposit`
+        require!(!escrow.block_on_metadata_change, EscrowError::MissingMetadataAccount);
+
+        // Every NFT in the requested slice that has not been deposited yet, in index order
+        let side_count = if is_initializer { escrow.initializer_nft_count } else { escrow.taker_nft_count };
+        let pending = chunk_indices(&escrow.pending_deposits(is_initializer), side_count, start_index, count)?;
+        require!(!pending.is_empty(), EscrowError::NftAlreadyDeposited);
+
+        let deposited_mints = deposit_pending_nfts(
+            escrow,
+            is_initializer,
+            &pending,
+            ctx.accounts.depositor.to_account_info(),
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        // Mark every NFT in the slice as deposited; the side is complete once no slice is left
+        let escrow = &mut ctx.accounts.escrow_account;
+        let deposited_at = Clock::get()?.unix_timestamp;
+        escrow.last_activity_at = deposited_at;
+        for (&nft_index, &mint) in pending.iter().zip(deposited_mints.iter()) {
+            escrow.record_vault_funder(is_initializer, nft_index, ctx.accounts.depositor.key());
+            escrow.record_deposit_time(is_initializer, nft_index, deposited_at);
+
+            emit!(NftDeposited {
+                escrow: escrow.key(),
+                depositor: ctx.accounts.depositor.key(),
+                mint,
+                is_initializer,
+                nft_index,
+                deposited_at,
+            });
+        }
+        if is_initializer {
+            for &nft_index in pending.iter() {
+                escrow.initializer_nft_deposited[nft_index as usize] = true;
+            }
+            escrow.initializer_deposited = escrow.initializer_nft_deposited.iter().all(|&d| d);
+            if escrow.initializer_deposited {
+                msg!("Initializer has deposited all NFTs");
+            } else {
+                msg!("Initializer deposited {} NFTs", pending.len());
+            }
+        } else {
+            for (&nft_index, &mint) in pending.iter().zip(deposited_mints.iter()) {
+                escrow.taker_nft_mints[nft_index as usize] = mint;
+                escrow.taker_nft_deposited[nft_index as usize] = true;
+            }
+            if escrow.taker_nft_deposited.iter().all(|&d| d) {
+                msg!("Taker has deposited all NFTs");
+            } else {
+                msg!("Taker deposited {} NFTs", pending.len());
+            }
+
+            // The taker's side is only complete once any SOL or token payment has landed too
+            if escrow.taker_side_complete() {
+                escrow.taker_deposited = true;
+            }
+        }
+
+        escrow.refresh_funding_status();
+        if escrow.status == EscrowStatus::Funded {
+            msg!("All NFTs have been deposited. Escrow is ready for completion.");
+        }
+        escrow.emit_deposit_progress(escrow.key());
+
+        Ok(())
+    }
+
+    pub fn deposit_sol(ctx: Context<DepositSol>) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+        // Deposits wait until the initializer has accepted any counter-offer
+        require!(!escrow.pending_acceptance, EscrowError::CounterPending);
+        require!(escrow.approved, EscrowError::EscrowNotApproved);
+        require!(ctx.accounts.taker.key() == escrow.taker, EscrowError::InvalidDepositor);
+        require!(escrow.sol_amount > 0, EscrowError::SolPaymentNotRequired);
+        require!(!escrow.sol_deposited, EscrowError::AlreadyDeposited);
+        // A stale quote has to be replaced with a new escrow rather than honoured late
+        require!(
+            escrow.sol_quote_valid(Clock::get()?.unix_timestamp),
+            EscrowError::SolQuoteExpired
+        );
+        // The taker's share of the fee is escrowed with the payment
+        let sol_due = escrow.taker_sol_due();
+        require!(
+            ctx.accounts.taker.lamports() >= sol_due,
+            EscrowError::InsufficientSolDeposit
+        );
+
+        // Transfer the lamports from the taker into the escrow PDA
+        let cpi_accounts = system_program::Transfer {
+            from: ctx.accounts.taker.to_account_info(),
+            to: escrow.to_account_info(),
+        };
+
+        let cpi_program = ctx.accounts.system_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+
+        system_program::transfer(cpi_ctx, sol_due)?;
+
+        escrow.sol_deposited = true;
+        escrow.last_activity_at = Clock::get()?.unix_timestamp;
+        msg!("Taker deposited {} lamports into escrow", sol_due);
+
+        // Check if all taker NFTs and any token leg have been deposited as well
+        if escrow.taker_side_complete() {
+            escrow.taker_deposited = true;
+            msg!("Taker has deposited all assets");
+        }
+
+        escrow.refresh_funding_status();
+        if escrow.status == EscrowStatus::Funded {
+            msg!("All assets have been deposited. Escrow is ready for completion.");
+        }
+
+        Ok(())
+    }
+
+    pub fn deposit_tokens(ctx: Context<DepositTokens>) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+        require!(!escrow.pending_acceptance, EscrowError::CounterPending);
+        require!(escrow.approved, EscrowError::EscrowNotApproved);
+        escrow.check_depositor(false, &ctx.accounts.depositor.key())?;
+        // Every transfer for this escrow must go through the token program it was created with
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+        require!(escrow.token_amount > 0, EscrowError::InvalidTokenAmount);
+        require!(!escrow.token_deposited, EscrowError::AlreadyDeposited);
+
+        // Unlike NFTs, the token leg is any exact amount of a fungible mint
+        require!(ctx.accounts.token_account.amount >= escrow.token_amount, EscrowError::InvalidTokenAmount);
+        require!(!ctx.accounts.token_account.is_frozen(), EscrowError::TokenAccountFrozen);
+        require!(ctx.accounts.vault_account.owner == escrow.key(), EscrowError::InvalidVaultAuthority);
+
+        let cpi_accounts = token_interface::TransferChecked {
+            from: ctx.accounts.token_account.to_account_info(),
+            mint: ctx.accounts.mint.to_account_info(),
+            to: ctx.accounts.vault_account.to_account_info(),
+            authority: ctx.accounts.depositor.to_account_info(),
         };
+        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+
+        token_interface::transfer_checked(cpi_ctx, escrow.token_amount, ctx.accounts.mint.decimals)?;
+
+        escrow.token_deposited = true;
+        escrow.token_vault_funder = ctx.accounts.depositor.key();
+        escrow.last_activity_at = Clock::get()?.unix_timestamp;
+        msg!("Taker deposited {} of token {} into escrow", escrow.token_amount, escrow.token_mint);
+
+        if escrow.taker_side_complete() {
+            escrow.taker_deposited = true;
+            msg!("Taker has deposited all assets");
+        }
+
+        escrow.refresh_funding_status();
+        if escrow.status == EscrowStatus::Funded {
+            msg!("All assets have been deposited. Escrow is ready for completion.");
+        }
+
+        Ok(())
+    }
+
+    pub fn reconcile(ctx: Context<Reconcile>) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        let before = escrow.aggregate_flags();
+
+        // The per-NFT arrays are authoritative; rebuild every aggregate flag from them
+        escrow.initializer_deposited = escrow.initializer_nft_deposited.iter().all(|&d| d);
+        escrow.taker_deposited = escrow.taker_side_complete();
+        escrow.refresh_collection_status();
+        // Once collection has started the status tracks collection, not funding
+        if !escrow.collection_started() && escrow.status != EscrowStatus::Cancelled {
+            escrow.refresh_funding_status();
+        }
+
+        let after = escrow.aggregate_flags();
+        if before != after {
+            msg!("Escrow flags reconciled");
+            emit!(FlagsReconciled {
+                escrow: escrow.key(),
+                before,
+                after,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn confirm_ready(ctx: Context<ConfirmReady>) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        // Readiness only means something once both sides are fully funded
+        require!(escrow.status == EscrowStatus::Funded, EscrowError::DepositsIncomplete);
+
+        if ctx.accounts.caller.key() == escrow.initializer {
+            escrow.initializer_ready = true;
+            msg!("Initializer confirmed ready: {}", escrow.initializer);
+        } else if ctx.accounts.caller.key() == escrow.taker {
+            escrow.taker_ready = true;
+            msg!("Taker confirmed ready: {}", escrow.taker);
+        } else {
+            return err!(EscrowError::InvalidCaller);
+        }
+
+        if escrow.initializer_ready && escrow.taker_ready {
+            msg!("Both parties are ready. Escrow can now be completed.");
+        }
+
+        Ok(())
+    }
+
+    pub fn complete<'info>(
+        ctx: Context<'_, '_, '_, 'info, Complete<'info>>,
+        is_initializer: bool,
+        nft_index: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &mut ctx.accounts.escrow_account;
         
-        // Verify the recipient token account belongs to the correct party
-        require!(ctx.accounts.recipient_token_account.owner == recipient_expected_owner, EscrowError::InvalidRecipient);
+        // Verify the escrow is initialized
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
         
-        // Transfer the NFT from the vault to the recipient
-        let seeds = &[
-            b"escrow",
-            escrow.initializer.as_ref(),
-            escrow.taker.as_ref(),
-            &[escrow.bump],
-        ];
-        let signer = &[&seeds[..]];
-        
-        let cpi_accounts = token::Transfer {
-            from: ctx.accounts.vault_account.to_account_info(),
-            to: ctx.accounts.recipient_token_account.to_account_info(),
-            authority: escrow.to_account_info(),
-        };
+        // Verify both parties have deposited all their NFTs
+        require!(escrow.initializer_deposited && escrow.taker_deposited, EscrowError::DepositsIncomplete);
+        require!(
+            escrow.status == EscrowStatus::Funded || escrow.status == EscrowStatus::Completing,
+            EscrowError::InvalidEscrowStatus
+        );
+
+        // Both parties must have confirmed before either can start collecting
+        require!(escrow.initializer_ready && escrow.taker_ready, EscrowError::PartiesNotReady);
+        // Every transfer for this escrow must go through the token program it was created with
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
         
-        let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, signer);
+        // Verify the caller is either the initializer or the taker
+        let is_initializer_caller = ctx.accounts.caller.key() == escrow.initializer;
+        require!(is_initializer_caller || ctx.accounts.caller.key() == escrow.taker, EscrowError::InvalidCaller);
+        // `is_initializer` picks the mint and recipient, so it must describe the caller's own claim
+        require!(is_initializer == is_initializer_caller, EscrowError::InvalidCaller);
         
-        token::transfer(cpi_ctx, 1)?;
+        // Verify the NFT index is valid
+        if is_initializer {
+            require!(nft_index < escrow.taker_nft_count, EscrowError::InvalidNftIndex);
+        } else {
+            require!(nft_index < escrow.initializer_nft_count, EscrowError::InvalidNftIndex);
+        }
         
-        msg!("Transferred NFT {} from escrow vault to recipient", expected_mint);
+        // Get the expected mint for this NFT
+        let expected_mint = if is_initializer {
+            escrow.taker_nft_mints[nft_index as usize]
+        } else {
+            escrow.initializer_nft_mints[nft_index as usize]
+        };
+        
+        // Check if this NFT, or the whole side it belongs to, has already been collected.
+        // This runs before the vault is touched: a repeated call finds the vault closed, and
+        // must still fail with NftAlreadyCollected rather than an account error.
+        if is_initializer {
+            require!(!escrow.taker_collected, EscrowError::NftAlreadyCollected);
+            require!(!escrow.taker_nft_collected[nft_index as usize], EscrowError::NftAlreadyCollected);
+        } else {
+            require!(!escrow.initializer_collected, EscrowError::NftAlreadyCollected);
+            require!(!escrow.initializer_nft_collected[nft_index as usize], EscrowError::NftAlreadyCollected);
+        }
         
-        // Mark this NFT as collected
+        // Mark this NFT as collected and take the completion lock, and write both through to
+        // account data before any CPI, so a re-entrant or repeated call sees them. A failure
+        // further down reverts them along with everything else.
         if is_initializer {
             escrow.taker_nft_collected[nft_index as usize] = true;
         } else {
             escrow.initializer_nft_collected[nft_index as usize] = true;
         }
+        escrow.in_progress = true;
+        escrow.exit(&crate::ID)?;
         
-        // Check if all NFTs have been collected
-        let mut all_initializer_nfts_collected = true;
-        for i in 0..escrow.initializer_nft_count as usize {
-            if !escrow.initializer_nft_collected[i] {
-                all_initializer_nfts_collected = false;
-                break;
+        // Verify the mint matches; compressed NFTs go through `complete_compressed`
+        require!(!escrow.is_compressed(!is_initializer, nft_index), EscrowError::CompressedNft);
+        require!(ctx.accounts.mint.key() == expected_mint, EscrowError::InvalidNftMint);
+        
+        // Bind the vault to this index: only the escrow's own vault for this index's mint will do,
+        // so a vault or recipient belonging to another index can't be swapped in
+        require!(
+            ctx.accounts.vault_account.key()
+                == escrow_vault_address(&escrow.key(), &expected_mint, ctx.accounts.token_program.key),
+            EscrowError::InvalidVaultAccounts
+        );
+        
+        // Verify the vault is the escrow's account for the correct mint
+        let vault = load_token_account(&ctx.accounts.vault_account, ctx.accounts.token_program.key)?;
+        require!(vault.mint == expected_mint, EscrowError::InvalidNftMint);
+        require!(vault.owner == escrow.key(), EscrowError::InvalidVaultAuthority);
+        
+        // Surface an empty or frozen vault before the token program rejects the transfer
+        let amount = escrow.nft_amount(!is_initializer, nft_index);
+        require!(vault.amount >= amount, EscrowError::VaultEmpty);
+        require!(!vault.is_frozen(), EscrowError::TokenAccountFrozen);
+        
+        // Determine the recipient based on which NFT is being collected
+        // Initializer NFTs go to taker, taker NFTs go to initializer.
+        // Each party collects the NFTs coming to them, so the recipient is always the caller.
+        let recipient_expected_owner = if is_initializer {
+            escrow.initializer
+        } else {
+            escrow.taker
+        };
+        require!(ctx.accounts.recipient.key() == recipient_expected_owner, EscrowError::InvalidRecipient);
+        // The NFT itself goes to the recipient's delivery address when they set one
+        let delivery_owner = escrow.delivery_owner(is_initializer);
+        let delivery_owner_info = if delivery_owner == recipient_expected_owner {
+            ctx.accounts.recipient.to_account_info()
+        } else {
+            let wallet = ctx.accounts.delivery_wallet.as_ref().ok_or(EscrowError::InvalidRecipient)?;
+            require!(wallet.key() == delivery_owner, EscrowError::InvalidRecipient);
+            wallet.to_account_info()
+        };
+        
+        // NFTs only ever land in the delivery owner's canonical ATA. The recipient pays for it:
+        // it is only created here when the recipient signs, and otherwise must already exist
+        require!(
+            ctx.accounts.recipient_token_account.key()
+                == associated_token::get_associated_token_address_with_program_id(
+                    &delivery_owner,
+                    &expected_mint,
+                    ctx.accounts.token_program.key,
+                ),
+            EscrowError::NotAssociatedTokenAccount
+        );
+        create_recipient_token_account(
+            ctx.accounts.recipient.to_account_info(),
+            delivery_owner_info,
+            ctx.accounts.recipient_token_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+        let recipient_token_account = load_token_account(
+            &ctx.accounts.recipient_token_account,
+            ctx.accounts.token_program.key,
+        )?;
+        
+        // Verify the recipient token account is for the correct mint and belongs to the correct party
+        require!(recipient_token_account.mint == expected_mint, EscrowError::InvalidNftMint);
+        require!(recipient_token_account.owner == delivery_owner, EscrowError::InvalidRecipient);
+        require!(!recipient_token_account.is_frozen(), EscrowError::TokenAccountFrozen);
+        
+        // Vault rent goes back to whoever funded the vault, not to the caller
+        let vault_funder = if is_initializer {
+            escrow.taker_vault_funders[nft_index as usize]
+        } else {
+            escrow.initializer_vault_funders[nft_index as usize]
+        };
+        require!(ctx.accounts.vault_funder.key() == vault_funder, EscrowError::InvalidRentRecipient);
+        
+        // Flag any edit to metadata fingerprinted at deposit time, and refuse the NFT if the
+        // escrow was set up to
+        let deposited_hash = if is_initializer {
+            escrow.taker_metadata_hashes[nft_index as usize]
+        } else {
+            escrow.initializer_metadata_hashes[nft_index as usize]
+        };
+        if deposited_hash != [0; 32] {
+            let metadata = if escrow.block_on_metadata_change {
+                Some(ctx.accounts.metadata_account.as_deref().ok_or(EscrowError::MissingMetadataAccount)?)
+            } else {
+                ctx.accounts.metadata_account.as_deref()
+            };
+            if let Some(metadata) = metadata {
+                require!(metadata.mint == expected_mint, EscrowError::InvalidMetadataAccount);
+                let current_hash = hash_metadata(metadata);
+                if current_hash != deposited_hash {
+                    msg!("Metadata of NFT {} changed since it was deposited", expected_mint);
+                    emit!(MetadataChanged {
+                        escrow: escrow.key(),
+                        mint: expected_mint,
+                        nft_index,
+                        deposited_hash,
+                        current_hash,
+                    });
+                    require!(!escrow.block_on_metadata_change, EscrowError::MetadataChangedSinceDeposit);
+                }
             }
         }
+
+        // The initializer is selling their NFTs for the SOL, so each one's creators are paid
+        // out of it before the NFT moves
+        if !is_initializer && escrow.royalties_due() {
+            let paid = pay_royalties(
+                escrow,
+                &expected_mint,
+                ctx.accounts.metadata_account.as_deref(),
+                ctx.remaining_accounts,
+            )?;
+            escrow.royalties_paid = escrow
+                .royalties_paid
+                .checked_add(paid)
+                .ok_or(EscrowError::RoyaltyOverflow)?;
+        }
         
-        let mut all_taker_nfts_collected = true;
-        for i in 0..escrow.taker_nft_count as usize {
-            if !escrow.taker_nft_collected[i] {
-                all_taker_nfts_collected = false;
-                break;
-            }
+        // Transfer the NFT from the vault to the recipient
+        transfer_from_vault(
+            escrow,
+            ctx.accounts.vault_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.recipient_token_account.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            amount,
+            ctx.accounts.mint.decimals,
+        )?;
+        
+        msg!("Transferred NFT {} from escrow vault to recipient", expected_mint);
+
+        // Close the emptied vault and return its rent to the party who deposited into it
+        if vault.amount == amount {
+            let vault_rent_recipient = ctx.accounts.vault_funder.to_account_info();
+
+            close_vault(
+                escrow,
+                ctx.accounts.vault_account.to_account_info(),
+                vault_rent_recipient,
+                ctx.accounts.token_program.to_account_info(),
+            )?;
+
+            msg!("Closed vault for NFT {}", expected_mint);
         }
         
-        // If all NFTs have been collected, close the escrow account
-        if all_initializer_nfts_collected && all_taker_nfts_collected {
-            // Close the escrow account and return rent to the initializer
-            ctx.accounts.close_escrow()?;
-            msg!("All NFTs have been collected. Escrow completed successfully.");
+        escrow.status = EscrowStatus::Completing;
+        escrow.last_collector = ctx.accounts.caller.key();
+        // Release the lock; it is persisted with the rest of the state on exit
+        escrow.in_progress = false;
+
+        emit!(NftCollected {
+            escrow: escrow.key(),
+            collector: ctx.accounts.caller.key(),
+            recipient: delivery_owner,
+            mint: expected_mint,
+            nft_index,
+            collected_at: Clock::get()?.unix_timestamp,
+        });
+        
+        // Each side is settled independently; the escrow is only done once both are. Whichever
+        // collection flips the last per-NFT flag marks it `Completed`, however the two parties
+        // interleaved, and `finalize` then releases the payments and closes it
+        if escrow.finish_collection() {
+            escrow.status = EscrowStatus::Completed;
+            msg!("All NFTs have been collected. The escrow is ready to be finalized.");
         } else {
-            let initializer_remaining = escrow.initializer_nft_count as usize - 
-                escrow.initializer_nft_collected.iter().filter(|&&x| x).count();
-            if initializer_remaining == 0 {
-                escrow.initializer_collected = true;
+            let initializer_remaining = escrow.initializer_nft_count - escrow.collected_count(true);
+            let taker_remaining = escrow.taker_nft_count - escrow.collected_count(false);
+            
+            if escrow.initializer_collected {
+                msg!("All initializer NFTs have been collected by the taker");
             }
-
-            let taker_remaining = escrow.taker_nft_count as usize - 
-                escrow.taker_nft_collected.iter().filter(|&&x| x).count();
-            if taker_remaining == 0 {
-                escrow.taker_collected = true;
+            if escrow.taker_collected {
+                msg!("All taker NFTs have been collected by the initializer");
             }
             
             msg!("NFT collected. Remaining NFTs to collect: {} initializer, {} taker",
@@ -291,206 +1444,5064 @@ pub mod swap_escrow {
         Ok(())
     }
 
-    pub fn cancel(ctx: Context<Cancel>) -> Result<()> {
-        let escrow = &ctx.accounts.escrow_account;
-        
-        // Verify the escrow is initialized
-        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
-        
-        // Verify the caller is the initializer
-        require!(ctx.accounts.initializer.key() == escrow.initializer, EscrowError::InvalidCanceller);
-        
-        // Check if any NFTs have been deposited
-        let can_cancel = !escrow.initializer_deposited && !escrow.taker_deposited;
-        
-        // Check if the escrow has timed out
-        let current_time = Clock::get()?.unix_timestamp;
-        let timeout_expired = current_time > escrow.created_at + escrow.timeout_in_seconds;
-        
-        require!(can_cancel || timeout_expired, EscrowError::CannotCancelAfterDeposit);
-        
-        if timeout_expired {
-            msg!("Escrow canceled due to timeout");
+    /// Collects the uncollected NFTs among indices `start_index..start_index + count` coming to
+    /// the caller. Large bundles are collected a slice at a time; see `SAFE_CHUNK_SIZE`. The
+    /// side only counts as collected, and the escrow is only marked `Completed` for `finalize`
+    /// to close, once every slice is done.
+    pub fn complete_all<'info>(
+        ctx: Context<'_, '_, '_, 'info, CompleteAll<'info>>,
+        is_initializer: bool,
+        start_index: u8,
+        count: u8,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(
+            escrow.status == EscrowStatus::Funded || escrow.status == EscrowStatus::Completing,
+            EscrowError::InvalidEscrowStatus
+        );
+        require!(escrow.initializer_ready && escrow.taker_ready, EscrowError::PartiesNotReady);
+        // Every transfer for this escrow must go through the token program it was created with
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
+        escrow.in_progress = true;
+        escrow.exit(&crate::ID)?;
+
+        // Like `complete`, each party only collects the NFTs coming to them
+        let is_initializer_caller = ctx.accounts.caller.key() == escrow.initializer;
+        require!(is_initializer == is_initializer_caller, EscrowError::InvalidCaller);
+        // Royalties need each NFT's metadata and creators, so those NFTs go through `complete`
+        require!(is_initializer || !escrow.royalties_due(), EscrowError::RoyaltyAccountMissing);
+        // Likewise for rechecking metadata fingerprinted at deposit time
+        require!(
+            !(escrow.block_on_metadata_change && escrow.tracks_metadata()),
+            EscrowError::MissingMetadataAccount
+        );
+
+        if is_initializer {
+            require!(!escrow.taker_collected, EscrowError::NftAlreadyCollected);
         } else {
-            msg!("Escrow canceled by initializer: {}", escrow.initializer);
+            require!(!escrow.initializer_collected, EscrowError::NftAlreadyCollected);
         }
-        
-        // The escrow account will be closed and rent returned to the initializer
-        
-        Ok(())
-    }
-}
 
-#[derive(Accounts)]
-#[instruction(initializer_nft_count: u8, taker_nft_count: u8, escrow_bump: u8)]
-pub struct Initialize<'info> {
-    #[account(mut)]
-    pub initializer: Signer<'info>,
-    /// CHECK: This is not dangerous because we don't read or write from this account
-    pub taker: UncheckedAccount<'info>,
-    #[account(
-        init,
+        // Every NFT in the requested slice that is still sitting in its vault, in index order
+        let (side_count, collected) = if is_initializer {
+            (escrow.taker_nft_count, &escrow.taker_nft_collected)
+        } else {
+            (escrow.initializer_nft_count, &escrow.initializer_nft_collected)
+        };
+        let uncollected: Vec<u8> = (0..side_count).filter(|&i| !collected[i as usize]).collect();
+        let pending = chunk_indices(&uncollected, side_count, start_index, count)?;
+        require!(!pending.is_empty(), EscrowError::NftAlreadyCollected);
+
+        // The NFTs land with the side's delivery address when it set one, not the caller
+        let recipient = escrow.delivery_owner(is_initializer);
+        let collected_mints = collect_pending_nfts(
+            escrow,
+            is_initializer,
+            &pending,
+            ctx.accounts.recipient.to_account_info(),
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        let collected_at = Clock::get()?.unix_timestamp;
+        for (&nft_index, &mint) in pending.iter().zip(collected_mints.iter()) {
+            if is_initializer {
+                escrow.taker_nft_collected[nft_index as usize] = true;
+            } else {
+                escrow.initializer_nft_collected[nft_index as usize] = true;
+            }
+
+            emit!(NftCollected {
+                escrow: escrow.key(),
+                collector: ctx.accounts.caller.key(),
+                recipient,
+                mint,
+                nft_index,
+                collected_at,
+            });
+        }
+        escrow.status = EscrowStatus::Completing;
+        escrow.last_collector = ctx.accounts.caller.key();
+        escrow.in_progress = false;
+
+        if escrow.finish_collection() {
+            // Releasing the payments and closing the escrow is left to `finalize`
+            escrow.status = EscrowStatus::Completed;
+            msg!("All NFTs have been collected. The escrow is ready to be finalized.");
+        } else {
+            msg!("Collected {} NFTs; the other side is still waiting to be collected", pending.len());
+        }
+
+        Ok(())
+    }
+
+    /// Releases the payments and closes an escrow once every NFT on both sides has been
+    /// collected, returning the rent to whoever paid for it. Anyone may call it, since
+    /// everything goes to recipients fixed at initialization.
+    pub fn finalize(ctx: Context<Finalize>) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(
+            escrow.status == EscrowStatus::Completed && escrow.initializer_collected && escrow.taker_collected,
+            EscrowError::InvalidEscrowStatus
+        );
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
+
+        let mut settled = EscrowSettled {
+            escrow: escrow.key(),
+            initializer: escrow.initializer,
+            taker: escrow.taker,
+            initializer_nft_mints: escrow.initializer_nft_mints.clone(),
+            taker_nft_mints: escrow.taker_nft_mints.clone(),
+            sol_amount: escrow.sol_amount,
+            rent_returned: 0,
+            created_at: escrow.created_at,
+            settled_at: Clock::get()?.unix_timestamp,
+        };
+
+        // Forward the taker's SOL and token payments to the initializer, then close the
+        // escrow account and return its rent to whoever paid for it
+        release_sol(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.fee_recipient.to_account_info(),
+            ctx.accounts.initializer.to_account_info(),
+        )?;
+        transfer_token_leg(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer.key(),
+            ctx.accounts.token_leg_mint.as_ref(),
+            ctx.accounts.token_leg_vault.as_ref(),
+            ctx.accounts.token_leg_destination.as_ref(),
+            ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+        settled.rent_returned = close_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.rent_payer.to_account_info(),
+        )?;
+        msg!("Escrow finalized by {}", ctx.accounts.caller.key());
+
+        emit!(settled);
+
+        Ok(())
+    }
+
+    pub fn withdraw_deposited(
+        ctx: Context<WithdrawDeposited>,
+        is_initializer: bool,
+        nft_index: u8,
+    ) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+
+        // Verify the depositor is correct and the counterparty has not completed their side
+        if is_initializer {
+            require!(ctx.accounts.depositor.key() == escrow.initializer, EscrowError::InvalidDepositor);
+            require!(nft_index < escrow.initializer_nft_count, EscrowError::InvalidNftIndex);
+            require!(!escrow.taker_deposited, EscrowError::CounterpartyAlreadyDeposited);
+            require!(escrow.initializer_nft_deposited[nft_index as usize], EscrowError::NftNotDeposited);
+        } else {
+            require!(ctx.accounts.depositor.key() == escrow.taker, EscrowError::InvalidDepositor);
+            require!(nft_index < escrow.taker_nft_count, EscrowError::InvalidNftIndex);
+            require!(!escrow.initializer_deposited, EscrowError::CounterpartyAlreadyDeposited);
+            require!(escrow.taker_nft_deposited[nft_index as usize], EscrowError::NftNotDeposited);
+        }
+
+        let expected_mint = if is_initializer {
+            escrow.initializer_nft_mints[nft_index as usize]
+        } else {
+            escrow.taker_nft_mints[nft_index as usize]
+        };
+
+        require!(!escrow.is_compressed(is_initializer, nft_index), EscrowError::CompressedNft);
+        require!(ctx.accounts.mint.key() == expected_mint, EscrowError::InvalidNftMint);
+        require!(ctx.accounts.vault_account.mint == expected_mint, EscrowError::InvalidNftMint);
+
+        // The vault's rent goes back to whoever paid for it, and the slot forgets them so the
+        // next deposit into it is recorded as funding its own vault
+        let vault_funder = escrow.take_vault_funder(is_initializer, nft_index);
+        require!(ctx.accounts.vault_funder.key() == vault_funder, EscrowError::InvalidRentRecipient);
+
+        // Transfer the NFT from the vault back to the depositor
+        transfer_from_vault(
+            escrow,
+            ctx.accounts.vault_account.to_account_info(),
+            ctx.accounts.mint.to_account_info(),
+            ctx.accounts.depositor_token_account.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+            escrow.nft_amount(is_initializer, nft_index),
+            ctx.accounts.mint.decimals,
+        )?;
+
+        msg!("Returned NFT {} from escrow vault to depositor", expected_mint);
+
+        close_vault(
+            escrow,
+            ctx.accounts.vault_account.to_account_info(),
+            ctx.accounts.vault_funder.to_account_info(),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        // Clear the deposited flags for this NFT and its side
+        escrow.record_deposit_time(is_initializer, nft_index, 0);
+        escrow.record_metadata_hash(is_initializer, nft_index, [0; 32]);
+        if is_initializer {
+            escrow.initializer_nft_deposited[nft_index as usize] = false;
+            escrow.initializer_deposited = false;
+        } else {
+            escrow.taker_nft_deposited[nft_index as usize] = false;
+            escrow.taker_deposited = false;
+
+            // Reopen a whitelisted slot so the taker can deposit a different NFT
+            if !escrow.allowed_taker_collections.is_empty() {
+                escrow.taker_nft_mints[nft_index as usize] = Pubkey::default();
+            }
+        }
+        escrow.refresh_funding_status();
+        escrow.emit_deposit_progress(escrow.key());
+
+        Ok(())
+    }
+
+    pub fn extend_timeout(ctx: Context<ExtendTimeout>, additional_seconds: i64) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(ctx.accounts.initializer.key() == escrow.initializer, EscrowError::InvalidCanceller);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+        require!(additional_seconds > 0, EscrowError::InvalidTimeout);
+
+        // Only a live escrow can be extended
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(
+            current_time <= escrow.expires_at()?,
+            EscrowError::CannotExtendExpiredEscrow
+        );
+
+        // Cap the cumulative extension
+        let total_extension = escrow
+            .timeout_extension_seconds
+            .checked_add(additional_seconds)
+            .ok_or(EscrowError::TimeoutOverflow)?;
+        require!(
+            total_extension <= MAX_TIMEOUT_EXTENSION_SECONDS,
+            EscrowError::TimeoutExtensionTooLarge
+        );
+
+        escrow.timeout_extension_seconds = total_extension;
+        escrow.timeout_in_seconds = escrow
+            .timeout_in_seconds
+            .checked_add(additional_seconds)
+            .ok_or(EscrowError::TimeoutOverflow)?;
+
+        let expires_at = escrow.expires_at()?;
+        msg!("Escrow timeout extended by {} seconds. New expiry: {}", additional_seconds, expires_at);
+
+        emit!(TimeoutExtended {
+            escrow: escrow.key(),
+            additional_seconds,
+            expires_at,
+        });
+
+        Ok(())
+    }
+
+    /// Rewrites a pre-versioning escrow in the current layout. Version 1 is the only versioned
+    /// layout so far, so the unversioned `LegacyEscrowAccount` is the only one to upgrade from.
+    pub fn migrate(ctx: Context<Migrate>) -> Result<()> {
+        let escrow_info = ctx.accounts.escrow_account.to_account_info();
+
+        let legacy = {
+            let data = escrow_info.try_borrow_data()?;
+
+            // Accounts already on the current layout have nothing to migrate
+            if let Ok(current) = EscrowAccount::try_deserialize(&mut &data[..]) {
+                require!(
+                    current.version != ESCROW_ACCOUNT_VERSION,
+                    EscrowError::AccountAlreadyCurrent
+                );
+            }
+
+            require!(
+                data.len() >= 8 && data[..8] == EscrowAccount::DISCRIMINATOR,
+                EscrowError::InvalidLegacyAccount
+            );
+            LegacyEscrowAccount::deserialize(&mut &data[8..])
+                .map_err(|_| error!(EscrowError::InvalidLegacyAccount))?
+        };
+
+        require!(legacy.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(
+            ctx.accounts.payer.key() == legacy.initializer || ctx.accounts.payer.key() == legacy.taker,
+            EscrowError::InvalidCaller
+        );
+
+        let escrow = legacy.upgrade();
+
+        // Grow the account to the current layout, with the caller covering any extra rent
+        resize_escrow(
+            escrow_info.clone(),
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            8 + EscrowAccount::space(escrow.initializer_nft_count, escrow.taker_nft_count),
+        )?;
+
+        let mut data = escrow_info.try_borrow_mut_data()?;
+        escrow.try_serialize(&mut &mut data[..])?;
+
+        msg!("Escrow {} migrated to layout version {}", escrow_info.key(), ESCROW_ACCOUNT_VERSION);
+
+        Ok(())
+    }
+
+    pub fn fund_rent(ctx: Context<FundRent>, target_space: u32) -> Result<()> {
+        let escrow_info = ctx.accounts.escrow_account.to_account_info();
+
+        // Fund for whichever is larger: the current size or the size about to be reallocated to
+        let space = escrow_info.data_len().max(target_space as usize);
+
+        // Escrowed SOL sits in the same account but is not rent
+        let reserved_lamports = {
+            let data = escrow_info.try_borrow_data()?;
+            match EscrowAccount::try_deserialize(&mut &data[..]) {
+                Ok(escrow) if escrow.sol_deposited => escrow.taker_sol_due(),
+                _ => 0,
+            }
+        };
+
+        let funded = top_up_rent(
+            escrow_info.clone(),
+            ctx.accounts.payer.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            space,
+            reserved_lamports,
+        )?;
+
+        msg!("Funded {} lamports of rent for {} bytes on {}", funded, space, escrow_info.key());
+
+        Ok(())
+    }
+
+    pub fn get_status(ctx: Context<GetStatus>) -> Result<EscrowProgress> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+
+        let progress = escrow.progress();
+
+        msg!(
+            "Initializer: {}/{} deposited, {}/{} collected",
+            progress.initializer_nfts_deposited,
+            escrow.initializer_nft_count,
+            progress.initializer_nfts_collected,
+            escrow.initializer_nft_count
+        );
+        msg!(
+            "Taker: {}/{} deposited, {}/{} collected",
+            progress.taker_nfts_deposited,
+            escrow.taker_nft_count,
+            progress.taker_nfts_collected,
+            escrow.taker_nft_count
+        );
+
+        Ok(progress)
+    }
+
+    pub fn get_time_remaining(ctx: Context<GetStatus>) -> Result<i64> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+
+        let remaining = escrow.seconds_until_expiry(Clock::get()?.unix_timestamp)?;
+        msg!("Seconds until the escrow can be cancelled: {}", remaining);
+
+        Ok(remaining)
+    }
+
+    /// Rent in lamports that closing the escrow and its open vaults would return.
+    pub fn get_reclaimable_rent(ctx: Context<GetStatus>) -> Result<u64> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+
+        let reclaimable = escrow.reclaimable_rent(&Rent::get()?);
+        msg!("Reclaimable rent: {} lamports across {} vaults", reclaimable, escrow.open_vault_count());
+
+        Ok(reclaimable)
+    }
+
+    /// Deposit and collection state of a single NFT slot, for clients that only track one.
+    pub fn check_nft_state(
+        ctx: Context<GetStatus>,
+        is_initializer: bool,
+        nft_index: u8,
+    ) -> Result<NftState> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        let nft_count = if is_initializer {
+            escrow.initializer_nft_count
+        } else {
+            escrow.taker_nft_count
+        };
+        require!(nft_index < nft_count, EscrowError::InvalidNftIndex);
+
+        let state = if is_initializer {
+            NftState {
+                deposited: escrow.initializer_nft_deposited[nft_index as usize],
+                collected: escrow.initializer_nft_collected[nft_index as usize],
+            }
+        } else {
+            NftState {
+                deposited: escrow.taker_nft_deposited[nft_index as usize],
+                collected: escrow.taker_nft_collected[nft_index as usize],
+            }
+        };
+        msg!(
+            "{} NFT {}: deposited {}, collected {}",
+            if is_initializer { "Initializer" } else { "Taker" },
+            nft_index,
+            state.deposited,
+            state.collected
+        );
+
+        Ok(state)
+    }
+
+    /// Test-only: moves the escrow's creation time so timeout paths (`cancel`,
+    /// `refund_expired`, `reap`) can be exercised without touching the validator clock.
+    /// A sliding timeout restarts from the same moment.
+    #[cfg(feature = "test")]
+    pub fn warp_created_at(ctx: Context<WarpCreatedAt>, created_at: i64) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+        escrow.created_at = created_at;
+        if escrow.sliding_timeout {
+            escrow.last_activity_at = created_at;
+        }
+
+        msg!("Escrow created_at warped to {}", created_at);
+
+        Ok(())
+    }
+
+    pub fn cancel_mutual<'info>(ctx: Context<'_, '_, '_, 'info, CancelMutual<'info>>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(
+            ctx.accounts.initializer.key() == escrow.initializer
+                && ctx.accounts.taker.key() == escrow.taker,
+            EscrowError::BothPartiesMustSign
+        );
+        // Once collection has started the swap can no longer be unwound
+        require!(
+            escrow.is_accepting_deposits() || escrow.status == EscrowStatus::Funded,
+            EscrowError::InvalidEscrowStatus
+        );
+
+        // Return every deposited NFT and close its vault
+        return_deposited_nfts(
+            escrow,
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        // Return any deposited token leg to the taker
+        transfer_token_leg(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.escrow_account.taker,
+            ctx.accounts.token_leg_mint.as_ref(),
+            ctx.accounts.token_leg_vault.as_ref(),
+            ctx.accounts.token_leg_destination.as_ref(),
+            ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        // Refund any SOL payment to the taker before the account is closed
+        if escrow.sol_deposited {
+            let sol_amount = escrow.taker_sol_due();
+            **ctx.accounts.escrow_account.to_account_info().lamports.borrow_mut() -= sol_amount;
+            **ctx.accounts.taker.to_account_info().lamports.borrow_mut() += sol_amount;
+            msg!("Refunded {} lamports to taker", sol_amount);
+        }
+
+        msg!("Escrow mutually canceled by {} and {}", escrow.initializer, escrow.taker);
+        ctx.accounts.escrow_account.status = EscrowStatus::Cancelled;
+
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+
+        // The escrow account will be closed and rent returned to whoever paid for it
+
+        Ok(())
+    }
+
+    /// Lets the arbiter named at initialization settle a fully funded escrow either way.
+    ///
+    /// For `Complete`, `remaining_accounts` holds (mint, vault, recipient ATA, vault funder)
+    /// for every uncollected taker NFT, then every uncollected initializer NFT, in index order.
+    /// The recipients' ATAs must already exist. For `Refund` it holds the groups described on
+    /// `return_deposited_nfts`.
+    pub fn arbiter_settle<'info>(
+        ctx: Context<'_, '_, '_, 'info, ArbiterSettle<'info>>,
+        decision: ArbiterDecision,
+    ) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        let arbiter = escrow.arbiter.ok_or(EscrowError::NoArbiter)?;
+        require!(ctx.accounts.arbiter.key() == arbiter, EscrowError::NotArbiter);
+        require!(escrow.initializer_deposited && escrow.taker_deposited, EscrowError::DepositsIncomplete);
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
+        escrow.in_progress = true;
+        escrow.exit(&crate::ID)?;
+
+        match decision {
+            ArbiterDecision::Complete => {
+                require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+                require!(
+                    escrow.status == EscrowStatus::Funded || escrow.status == EscrowStatus::Completing,
+                    EscrowError::InvalidEscrowStatus
+                );
+
+                collect_both_sides(
+                    escrow,
+                    ctx.accounts.initializer.to_account_info(),
+                    ctx.accounts.taker.to_account_info(),
+                    ctx.remaining_accounts,
+                    ctx.accounts.token_program.to_account_info(),
+                    ctx.accounts.associated_token_program.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                )?;
+
+                let escrow = &ctx.accounts.escrow_account;
+                let mut settled = EscrowSettled {
+                    escrow: escrow.key(),
+                    initializer: escrow.initializer,
+                    taker: escrow.taker,
+                    initializer_nft_mints: escrow.initializer_nft_mints.clone(),
+                    taker_nft_mints: escrow.taker_nft_mints.clone(),
+                    sol_amount: escrow.sol_amount,
+                    rent_returned: 0,
+                    created_at: escrow.created_at,
+                    settled_at: Clock::get()?.unix_timestamp,
+                };
+
+                release_sol(
+                    &ctx.accounts.escrow_account,
+                    ctx.accounts.fee_recipient.to_account_info(),
+                    ctx.accounts.initializer.to_account_info(),
+                )?;
+                transfer_token_leg(
+                    &ctx.accounts.escrow_account,
+                    ctx.accounts.initializer.key(),
+                    ctx.accounts.token_leg_mint.as_ref(),
+                    ctx.accounts.token_leg_vault.as_ref(),
+                    ctx.accounts.token_leg_destination.as_ref(),
+                    ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+                    ctx.accounts.token_program.to_account_info(),
+                )?;
+                release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+                unlist_escrow(
+                    &ctx.accounts.escrow_account,
+                    ctx.accounts.initializer_escrow_list.as_mut(),
+                    ctx.accounts.taker_escrow_list.as_mut(),
+                )?;
+                settled.rent_returned = close_escrow(
+                    &ctx.accounts.escrow_account,
+                    ctx.accounts.rent_payer.to_account_info(),
+                )?;
+                msg!("Escrow completed by arbiter {}", arbiter);
+
+                emit!(settled);
+            }
+            ArbiterDecision::Refund => {
+                // Once collection has started the swap can no longer be unwound
+                require!(escrow.status == EscrowStatus::Funded, EscrowError::InvalidEscrowStatus);
+
+                return_deposited_nfts(
+                    escrow,
+                    ctx.remaining_accounts,
+                    ctx.accounts.token_program.to_account_info(),
+                )?;
+                transfer_token_leg(
+                    &ctx.accounts.escrow_account,
+                    ctx.accounts.escrow_account.taker,
+                    ctx.accounts.token_leg_mint.as_ref(),
+                    ctx.accounts.token_leg_vault.as_ref(),
+                    ctx.accounts.token_leg_destination.as_ref(),
+                    ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+                    ctx.accounts.token_program.to_account_info(),
+                )?;
+
+                let escrow = &mut ctx.accounts.escrow_account;
+                if escrow.sol_deposited {
+                    let sol_amount = escrow.taker_sol_due();
+                    **escrow.to_account_info().lamports.borrow_mut() -= sol_amount;
+                    **ctx.accounts.taker.to_account_info().lamports.borrow_mut() += sol_amount;
+                    msg!("Refunded {} lamports to taker", sol_amount);
+                }
+                escrow.status = EscrowStatus::Cancelled;
+
+                release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+                unlist_escrow(
+                    &ctx.accounts.escrow_account,
+                    ctx.accounts.initializer_escrow_list.as_mut(),
+                    ctx.accounts.taker_escrow_list.as_mut(),
+                )?;
+                close_escrow(&ctx.accounts.escrow_account, ctx.accounts.rent_payer.to_account_info())?;
+                msg!("Escrow refunded by arbiter {}", arbiter);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Settles a funded escrow in one call: every NFT moves to its new owner, the payments are
+    /// released and the escrow closes. Anyone may call it once both parties have confirmed.
+    ///
+    /// `remaining_accounts` holds (mint, vault, recipient ATA, vault funder) for every
+    /// uncollected taker NFT, then every uncollected initializer NFT, in index order. The
+    /// recipients' ATAs must already exist.
+    ///
+    /// Aggregators may call this through CPI: `caller` can be a PDA signing with
+    /// `invoke_signed`, as it pays for nothing and receives nothing.
+    pub fn settle<'info>(ctx: Context<'_, '_, '_, 'info, Settle<'info>>) -> Result<()> {
+        require!(!ctx.accounts.config.paused, EscrowError::ProgramPaused);
+
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.initializer_deposited && escrow.taker_deposited, EscrowError::DepositsIncomplete);
+        require!(
+            escrow.status == EscrowStatus::Funded || escrow.status == EscrowStatus::Completing,
+            EscrowError::InvalidEscrowStatus
+        );
+        require!(escrow.initializer_ready && escrow.taker_ready, EscrowError::PartiesNotReady);
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
+        escrow.in_progress = true;
+        escrow.exit(&crate::ID)?;
+
+        collect_both_sides(
+            escrow,
+            ctx.accounts.initializer.to_account_info(),
+            ctx.accounts.taker.to_account_info(),
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.associated_token_program.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+        )?;
+
+        let escrow = &ctx.accounts.escrow_account;
+        let mut settled = EscrowSettled {
+            escrow: escrow.key(),
+            initializer: escrow.initializer,
+            taker: escrow.taker,
+            initializer_nft_mints: escrow.initializer_nft_mints.clone(),
+            taker_nft_mints: escrow.taker_nft_mints.clone(),
+            sol_amount: escrow.sol_amount,
+            rent_returned: 0,
+            created_at: escrow.created_at,
+            settled_at: Clock::get()?.unix_timestamp,
+        };
+
+        release_sol(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.fee_recipient.to_account_info(),
+            ctx.accounts.initializer.to_account_info(),
+        )?;
+        transfer_token_leg(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer.key(),
+            ctx.accounts.token_leg_mint.as_ref(),
+            ctx.accounts.token_leg_vault.as_ref(),
+            ctx.accounts.token_leg_destination.as_ref(),
+            ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+        settled.rent_returned = close_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.rent_payer.to_account_info(),
+        )?;
+        msg!("Escrow settled by {}", ctx.accounts.caller.key());
+
+        emit!(settled);
+
+        Ok(())
+    }
+
+    pub fn refund_expired<'info>(ctx: Context<'_, '_, '_, 'info, RefundExpired<'info>>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        // Once collection has started the swap can no longer be unwound
+        require!(
+            escrow.is_accepting_deposits() || escrow.status == EscrowStatus::Funded,
+            EscrowError::InvalidEscrowStatus
+        );
+
+        let current_time = Clock::get()?.unix_timestamp;
+        require!(current_time > escrow.expires_at()?, EscrowError::EscrowNotExpired);
+        escrow.check_keeper_grace(&ctx.accounts.caller.key(), current_time)?;
+
+        // Return every deposited NFT regardless of how far each side got
+        return_deposited_nfts(
+            escrow,
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        // Return any deposited token leg to the taker
+        transfer_token_leg(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.escrow_account.taker,
+            ctx.accounts.token_leg_mint.as_ref(),
+            ctx.accounts.token_leg_vault.as_ref(),
+            ctx.accounts.token_leg_destination.as_ref(),
+            ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        // Refund any SOL payment to the taker before the account is closed
+        if escrow.sol_deposited {
+            let taker = ctx.accounts.taker.as_ref().ok_or(EscrowError::InvalidRecipient)?;
+            let sol_amount = escrow.taker_sol_due();
+            **ctx.accounts.escrow_account.to_account_info().lamports.borrow_mut() -= sol_amount;
+            **taker.to_account_info().lamports.borrow_mut() += sol_amount;
+            msg!("Refunded {} lamports to taker", sol_amount);
+        }
+
+        msg!("Expired escrow refunded by {}", ctx.accounts.caller.key());
+        ctx.accounts.escrow_account.status = EscrowStatus::Cancelled;
+
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+
+        // The escrow account will be closed and rent returned to whoever paid for it
+
+        Ok(())
+    }
+
+    /// Lets a party take back what they still have in an expired escrow that can never
+    /// complete because an NFT owed to one side is gone from its vault (burned, for example).
+    ///
+    /// The first claim proves the escrow is stuck: `remaining_accounts` starts with the empty
+    /// vault of an NFT that is deposited but not yet collected. Later claims skip that account.
+    /// Then come (mint, vault, depositor token account, vault funder) groups for each of the
+    /// caller's NFTs still in a vault, in index order. A claiming taker also gets back any SOL
+    /// and token payment. The escrow closes once both sides have nothing left in it.
+    pub fn claim_unmatched<'info>(
+        ctx: Context<'_, '_, '_, 'info, ClaimUnmatched<'info>>,
+        is_initializer: bool,
+    ) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        let party = if is_initializer { escrow.initializer } else { escrow.taker };
+        require!(ctx.accounts.caller.key() == party, EscrowError::InvalidCaller);
+        require!(!escrow.in_progress, EscrowError::CompletionInProgress);
+        require!(
+            Clock::get()?.unix_timestamp > escrow.expires_at()?,
+            EscrowError::EscrowNotExpired
+        );
+        escrow.check_token_program(ctx.accounts.token_program.key)?;
+
+        // An escrow already found unfulfillable is left Cancelled until both sides have claimed
+        let groups = if escrow.status == EscrowStatus::Cancelled {
+            ctx.remaining_accounts
+        } else {
+            require!(
+                escrow.status == EscrowStatus::Funded || escrow.status == EscrowStatus::Completing,
+                EscrowError::InvalidEscrowStatus
+            );
+            let (proof, groups) = ctx
+                .remaining_accounts
+                .split_first()
+                .ok_or(EscrowError::SwapUnfulfillable)?;
+            check_unfulfillable(escrow, proof, ctx.accounts.token_program.key)?;
+            groups
+        };
+
+        // Everything of the caller's that is still sitting in a vault
+        let unmatched = escrow.unmatched_nfts(is_initializer);
+        require!(
+            unmatched.iter().all(|&i| !escrow.is_compressed(is_initializer, i)),
+            EscrowError::CompressedNftsHeld
+        );
+        let funders = if is_initializer { &escrow.initializer_vault_funders } else { &escrow.taker_vault_funders };
+        let mints = if is_initializer { &escrow.initializer_nft_mints } else { &escrow.taker_nft_mints };
+        let nfts = unmatched
+            .iter()
+            .map(|&i| (mints[i as usize], party, funders[i as usize]))
+            .collect();
+        return_nfts(escrow, nfts, groups, ctx.accounts.token_program.to_account_info())?;
+
+        if !is_initializer {
+            // The taker's payments never reached the initializer, so they go back too
+            transfer_token_leg(
+                &ctx.accounts.escrow_account,
+                party,
+                ctx.accounts.token_leg_mint.as_ref(),
+                ctx.accounts.token_leg_vault.as_ref(),
+                ctx.accounts.token_leg_destination.as_ref(),
+                ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+                ctx.accounts.token_program.to_account_info(),
+            )?;
+            if escrow.sol_deposited {
+                let sol_amount = escrow.taker_sol_due();
+                **ctx.accounts.escrow_account.to_account_info().lamports.borrow_mut() -= sol_amount;
+                **ctx.accounts.caller.to_account_info().lamports.borrow_mut() += sol_amount;
+                msg!("Refunded {} lamports to taker", sol_amount);
+            }
+        }
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        for &nft_index in unmatched.iter() {
+            if is_initializer {
+                escrow.initializer_nft_deposited[nft_index as usize] = false;
+            } else {
+                escrow.taker_nft_deposited[nft_index as usize] = false;
+            }
+            escrow.record_deposit_time(is_initializer, nft_index, 0);
+            escrow.record_metadata_hash(is_initializer, nft_index, [0; 32]);
+        }
+        if is_initializer {
+            escrow.initializer_deposited = false;
+        } else {
+            escrow.taker_deposited = false;
+            escrow.sol_deposited = false;
+            escrow.token_deposited = false;
+        }
+        escrow.status = EscrowStatus::Cancelled;
+
+        msg!("{} reclaimed {} unmatched NFTs", party, unmatched.len());
+
+        // Close once neither side has anything left in the escrow
+        if escrow.unmatched_nfts(true).is_empty() && escrow.unmatched_nfts(false).is_empty() {
+            release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+            unlist_escrow(
+                &ctx.accounts.escrow_account,
+                ctx.accounts.initializer_escrow_list.as_mut(),
+                ctx.accounts.taker_escrow_list.as_mut(),
+            )?;
+            close_escrow(&ctx.accounts.escrow_account, ctx.accounts.rent_payer.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    pub fn reap<'info>(ctx: Context<'_, '_, '_, 'info, Reap<'info>>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+
+        // Only escrows the taker abandoned can be reaped: timed out with nothing from the taker
+        let current_time = Clock::get()?.unix_timestamp;
+        let taker_deposited_anything = escrow.taker_nft_deposited.iter().any(|&d| d)
+            || escrow.sol_deposited
+            || escrow.token_deposited;
+        require!(
+            current_time > escrow.expires_at()? && !taker_deposited_anything,
+            EscrowError::NotReapable
+        );
+        escrow.check_keeper_grace(&ctx.accounts.keeper.key(), current_time)?;
+
+        // Only the initializer can have deposits, so this returns their NFTs
+        return_deposited_nfts(
+            escrow,
+            ctx.remaining_accounts,
+            ctx.accounts.token_program.to_account_info(),
+        )?;
+
+        // The keeper's crank fee comes out of the rent being returned
+        let escrow_info = ctx.accounts.escrow_account.to_account_info();
+        let crank_fee = ctx.accounts.config.crank_fee_lamports.min(escrow_info.lamports());
+        if crank_fee > 0 {
+            **escrow_info.lamports.borrow_mut() -= crank_fee;
+            **ctx.accounts.keeper.to_account_info().lamports.borrow_mut() += crank_fee;
+        }
+
+        msg!("Abandoned escrow reaped by {} for {} lamports", ctx.accounts.keeper.key(), crank_fee);
+        ctx.accounts.escrow_account.status = EscrowStatus::Cancelled;
+
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+
+        // The escrow account will be closed and rent returned to whoever paid for it
+
+        Ok(())
+    }
+
+    /// Sets or clears where the caller's incoming NFTs are delivered. Only possible while
+    /// nothing is deposited, so neither party can redirect assets once the deal is underway.
+    pub fn set_delivery_address(
+        ctx: Context<SetDeliveryAddress>,
+        delivery_address: Option<Pubkey>,
+    ) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+        let party = ctx.accounts.party.key();
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.status == EscrowStatus::Created, EscrowError::DeliveryAddressLocked);
+
+        if party == escrow.initializer {
+            escrow.initializer_delivery_address = delivery_address;
+        } else if party == escrow.taker {
+            escrow.taker_delivery_address = delivery_address;
+        } else {
+            return err!(EscrowError::InvalidCaller);
+        }
+
+        msg!("Delivery address for {} set to {:?}", party, delivery_address);
+
+        Ok(())
+    }
+
+    /// Opens an escrow created with an approver to deposits. Only that approver may call it.
+    pub fn approve(ctx: Context<Approve>) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.approver == Some(ctx.accounts.approver.key()), EscrowError::NotApprover);
+        require!(escrow.status == EscrowStatus::Created, EscrowError::InvalidEscrowStatus);
+
+        escrow.approved = true;
+
+        msg!("Escrow approved by {}", ctx.accounts.approver.key());
+
+        Ok(())
+    }
+
+    pub fn reject(ctx: Context<Reject>) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(ctx.accounts.taker.key() == escrow.taker, EscrowError::OnlyTakerCanReject);
+        // Rejection is only possible before anything has been deposited
+        require!(escrow.status == EscrowStatus::Created, EscrowError::CannotCancelAfterDeposit);
+
+        escrow.status = EscrowStatus::Cancelled;
+
+        msg!("Escrow rejected by taker: {}", escrow.taker);
+
+        emit!(EscrowRejected {
+            escrow: escrow.key(),
+            initializer: escrow.initializer,
+            taker: escrow.taker,
+        });
+
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+
+        // The escrow account will be closed and rent returned to whoever paid for it
+
+        Ok(())
+    }
+
+    pub fn propose_counter<'info>(
+        ctx: Context<'_, '_, '_, 'info, ProposeCounter<'info>>,
+        is_initializer_side: bool,
+        nft_count: u8,
+    ) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(ctx.accounts.taker.key() == escrow.taker, EscrowError::OnlyTakerCanCounter);
+        // Terms can only be renegotiated before anything has been deposited
+        require!(escrow.status == EscrowStatus::Created, EscrowError::CannotCounterAfterDeposit);
+
+        // Same count rules as initialization: the taker may offer no NFTs when paying in SOL
+        if is_initializer_side {
+            require!(
+                nft_count > 0 && nft_count <= MAX_NFTS_PER_SIDE,
+                EscrowError::InvalidNftCount
+            );
+        } else {
+            require!(
+                (nft_count > 0 || escrow.sol_amount > 0) && nft_count <= MAX_NFTS_PER_SIDE,
+                EscrowError::InvalidNftCount
+            );
+        }
+
+        // The replacement mints for the chosen side are supplied via remaining_accounts
+        require!(
+            ctx.remaining_accounts.len() == nft_count as usize,
+            EscrowError::MissingMintAccounts
+        );
+        let new_mints: Vec<Pubkey> = ctx.remaining_accounts.iter().map(|a| a.key()).collect();
+
+        // Mints must stay unique within the new list and across both sides
+        let other_side_mints = if is_initializer_side {
+            &escrow.taker_nft_mints
+        } else {
+            &escrow.initializer_nft_mints
+        };
+        for i in 0..new_mints.len() {
+            for j in (i + 1)..new_mints.len() {
+                require!(new_mints[i] != new_mints[j], EscrowError::DuplicateMint);
+            }
+            require!(!other_side_mints.contains(&new_mints[i]), EscrowError::CrossSideDuplicateMint);
+        }
+
+        // Resize the escrow for the new counts, with the taker covering any extra rent
+        let (initializer_nft_count, taker_nft_count) = if is_initializer_side {
+            (nft_count, escrow.taker_nft_count)
+        } else {
+            (escrow.initializer_nft_count, nft_count)
+        };
+        resize_escrow(
+            escrow.to_account_info(),
+            ctx.accounts.taker.to_account_info(),
+            ctx.accounts.system_program.to_account_info(),
+            8 + EscrowAccount::space(initializer_nft_count, taker_nft_count),
+        )?;
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        escrow.replace_side_mints(is_initializer_side, new_mints);
+        if !is_initializer_side {
+            // An explicit taker list replaces any collection whitelist
+            escrow.allowed_taker_collections.clear();
+        }
+        escrow.pending_acceptance = true;
+
+        msg!(
+            "Taker proposed {} NFTs for the {} side",
+            nft_count,
+            if is_initializer_side { "initializer" } else { "taker" }
+        );
+
+        Ok(())
+    }
+
+    /// Accepts the pending counter-offer. Passing `taker_nft_count` also trims the taker's side
+    /// down to its first `taker_nft_count` NFTs, so the parties can settle on a smaller deal.
+    pub fn accept_counter(ctx: Context<AcceptCounter>, taker_nft_count: Option<u8>) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(ctx.accounts.initializer.key() == escrow.initializer, EscrowError::InvalidCanceller);
+        require!(escrow.pending_acceptance, EscrowError::NoCounterPending);
+
+        if let Some(nft_count) = taker_nft_count {
+            // Only a reduction, and the taker must still be giving something
+            require!(
+                nft_count < escrow.taker_nft_count
+                    && (nft_count > 0 || escrow.sol_amount > 0 || escrow.token_amount > 0),
+                EscrowError::InvalidNftCount
+            );
+            // An NFT already sitting in a dropped slot has to be withdrawn first
+            require!(
+                !escrow.taker_nft_deposited[nft_count as usize..].contains(&true),
+                EscrowError::CannotRemoveDepositedSlot
+            );
+
+            let initializer_nft_count = escrow.initializer_nft_count;
+            let removed = escrow.taker_nft_count - nft_count;
+            ctx.accounts.escrow_account.truncate_taker_slots(nft_count);
+            resize_escrow(
+                ctx.accounts.escrow_account.to_account_info(),
+                ctx.accounts.initializer.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+                8 + EscrowAccount::space(initializer_nft_count, nft_count),
+            )?;
+
+            msg!("Taker side reduced by {} NFTs to {}", removed, nft_count);
+        }
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        escrow.pending_acceptance = false;
+        // Trimming may leave only slots that are already filled
+        escrow.taker_deposited = escrow.taker_side_complete();
+        escrow.refresh_funding_status();
+
+        msg!("Counter-offer accepted by initializer: {}", escrow.initializer);
+
+        Ok(())
+    }
+
+    /// Hands the initializer's position in an open offer to `new_initializer`, e.g. a buyer of
+    /// the position or a multisig. Only possible before anything has been deposited.
+    pub fn transfer_initializer(ctx: Context<TransferInitializer>, new_initializer: Pubkey) -> Result<()> {
+        let escrow = &ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(ctx.accounts.initializer.key() == escrow.initializer, EscrowError::InvalidCanceller);
+        // Open offers are the nonce-seeded variant; directed escrows pin the pair in their seeds
+        require!(escrow.is_open_offer, EscrowError::NotAnOpenOffer);
+        require!(
+            escrow.status == EscrowStatus::Created
+                && !escrow.initializer_nft_deposited.contains(&true)
+                && !escrow.taker_nft_deposited.contains(&true)
+                && !escrow.sol_deposited
+                && !escrow.token_deposited,
+            EscrowError::CannotTransferAfterDeposit
+        );
+        require!(new_initializer != escrow.taker, EscrowError::SelfEscrowNotAllowed);
+
+        // Move the escrow's slot from the old initializer's counter to the new one's
+        if escrow.counted_as_active {
+            let new_stats = ctx
+                .accounts
+                .new_initializer_stats
+                .as_mut()
+                .ok_or(EscrowError::MissingInitializerStats)?;
+            let bump = Pubkey::find_program_address(
+                &[b"initializer_stats".as_ref(), new_initializer.as_ref()],
+                &crate::ID,
+            )
+            .1;
+            open_active_slot(new_stats, new_initializer, bump, ctx.accounts.config.max_active_escrows)?;
+        }
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+
+        let escrow_key = ctx.accounts.escrow_account.key();
+        // An open offer is never in a taker's list, so only the initializer's list moves
+        unlist_escrow(&ctx.accounts.escrow_account, ctx.accounts.initializer_escrow_list.as_mut(), None)?;
+        ctx.accounts.escrow_account.listed_initializer = false;
+        if let Some(list) = ctx.accounts.new_initializer_escrow_list.as_mut() {
+            require!(
+                ctx.accounts.new_initializer_account.is_some(),
+                EscrowError::EscrowListOwnerMustSign
+            );
+            list_escrow(
+                list,
+                escrow_key,
+                ctx.accounts.initializer.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            )?;
+            ctx.accounts.escrow_account.listed_initializer = true;
+        }
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        let previous_initializer = escrow.initializer;
+        escrow.initializer = new_initializer;
+        // The rent goes back to whoever holds the position, unless a sponsor paid for it
+        if escrow.rent_payer == previous_initializer {
+            escrow.rent_payer = new_initializer;
+        }
+        // A delegate registered for the previous initializer does not carry over
+        escrow.initializer_delegate = None;
+
+        msg!("Initializer changed from {} to {}", previous_initializer, new_initializer);
+
+        Ok(())
+    }
+
+    pub fn set_taker(ctx: Context<SetTaker>, new_taker: Pubkey) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(ctx.accounts.initializer.key() == escrow.initializer, EscrowError::InvalidCanceller);
+        // Directed escrows bake the taker into their PDA seeds; only nonce-seeded offers can move
+        require!(escrow.is_open_offer, EscrowError::NotAnOpenOffer);
+        require!(
+            escrow.status == EscrowStatus::Created
+                && !escrow.initializer_deposited
+                && !escrow.taker_deposited,
+            EscrowError::CannotChangeTakerAfterDeposit
+        );
+        require!(new_taker != escrow.initializer, EscrowError::SelfEscrowNotAllowed);
+
+        let previous_taker = escrow.taker;
+        escrow.taker = new_taker;
+        // A delegate registered for the previous taker does not carry over
+        escrow.taker_delegate = None;
+
+        msg!("Taker changed from {} to {}", previous_taker, new_taker);
+
+        Ok(())
+    }
+
+    pub fn cancel<'info>(ctx: Context<'_, '_, '_, 'info, Cancel<'info>>) -> Result<()> {
+        let escrow = &mut ctx.accounts.escrow_account;
+        
+        // Verify the escrow is initialized
+        require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+        require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+        
+        // Verify the caller is the initializer
+        require!(ctx.accounts.initializer.key() == escrow.initializer, EscrowError::InvalidCanceller);
+        
+        // Check if any NFTs have been deposited. A partially funded escrow still holds NFTs in
+        // its vaults, which closing it here would strand, even once it has timed out.
+        let can_cancel = !escrow.initializer_deposited
+            && !escrow.taker_deposited
+            && !escrow.initializer_nft_deposited.contains(&true)
+            && !escrow.taker_nft_deposited.contains(&true);
+        require!(can_cancel, EscrowError::CannotCancelAfterDeposit);
+        
+        // Check if the escrow has timed out
+        let current_time = Clock::get()?.unix_timestamp;
+        let timeout_expired = current_time > escrow.expires_at()?;
+        
+        if timeout_expired {
+            msg!("Escrow canceled due to timeout");
+        } else {
+            msg!("Escrow canceled by initializer: {}", escrow.initializer);
+        }
+
+        // Refund any SOL payment to the taker before the account is closed
+        if escrow.sol_deposited {
+            let taker = ctx.accounts.taker.as_ref().ok_or(EscrowError::InvalidRecipient)?;
+            let sol_amount = escrow.taker_sol_due();
+            **escrow.to_account_info().lamports.borrow_mut() -= sol_amount;
+            **taker.to_account_info().lamports.borrow_mut() += sol_amount;
+            msg!("Refunded {} lamports to taker", sol_amount);
+        }
+
+        // Return any deposited token leg to the taker
+        if escrow.token_deposited {
+            let token_program = ctx
+                .accounts
+                .token_program
+                .as_ref()
+                .ok_or(EscrowError::MissingTokenLegAccounts)?;
+            transfer_token_leg(
+                &ctx.accounts.escrow_account,
+                ctx.accounts.escrow_account.taker,
+                ctx.accounts.token_leg_mint.as_ref(),
+                ctx.accounts.token_leg_vault.as_ref(),
+                ctx.accounts.token_leg_destination.as_ref(),
+                ctx.accounts.token_leg_vault_funder.as_ref().map(|a| a.to_account_info()),
+                token_program.to_account_info(),
+            )?;
+        }
+
+        // Close any empty vaults still open from earlier deposits
+        if !ctx.remaining_accounts.is_empty() {
+            let token_program = ctx
+                .accounts
+                .token_program
+                .as_ref()
+                .ok_or(EscrowError::InvalidVaultAccounts)?;
+            close_stray_vaults(
+                &ctx.accounts.escrow_account,
+                ctx.remaining_accounts,
+                token_program.to_account_info(),
+            )?;
+        }
+
+        let escrow = &mut ctx.accounts.escrow_account;
+        escrow.status = EscrowStatus::Cancelled;
+        
+        release_active_slot(&ctx.accounts.escrow_account, ctx.accounts.initializer_stats.as_mut())?;
+        unlist_escrow(
+            &ctx.accounts.escrow_account,
+            ctx.accounts.initializer_escrow_list.as_mut(),
+            ctx.accounts.taker_escrow_list.as_mut(),
+        )?;
+
+        // The escrow account will be closed and rent returned to whoever paid for it
+        
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        payer = admin,
+        space = Config::SPACE,
+        seeds = [b"config".as_ref()],
+        bump,
+    )]
+    pub config: Account<'info, Config>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetPaused<'info> {
+    /// The config admin or pauser
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = authority.key() == config.admin || authority.key() == config.pauser @ EscrowError::NotPauser
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetAdmin<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = admin.key() == config.admin @ EscrowError::NotAdmin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetPauser<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = admin.key() == config.admin @ EscrowError::NotAdmin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetCrankFee<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = admin.key() == config.admin @ EscrowError::NotAdmin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxActiveEscrows<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = admin.key() == config.admin @ EscrowError::NotAdmin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetAllowedTokenPrograms<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = admin.key() == config.admin @ EscrowError::NotAdmin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct SetCircuitBreaker<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = admin.key() == config.admin @ EscrowError::NotAdmin
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct ReportCompletionFailure<'info> {
+    /// The config's failure reporter or admin
+    pub reporter: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config".as_ref()],
+        bump = config.bump,
+        constraint = reporter.key() == config.failure_reporter || reporter.key() == config.admin @ EscrowError::NotFailureReporter
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct CreateEscrowList<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + UserEscrowList::space(0),
+        seeds = [b"escrow_list".as_ref(), owner.key().as_ref()],
+        bump,
+    )]
+    pub escrow_list: Account<'info, UserEscrowList>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64, initializer_nft_count: u8, taker_nft_count: u8)]
+pub struct Initialize<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is not dangerous because we don't read or write from this account
+    pub taker: UncheckedAccount<'info>,
+    /// Pays the escrow account's rent and gets it back when the escrow closes. Usually the
+    /// initializer, but a relayer can sponsor the account instead.
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + EscrowAccount::space(initializer_nft_count, taker_nft_count),
+        seeds = [
+            b"escrow".as_ref(),
+            initializer.key().as_ref(),
+            taker.key().as_ref(),
+            nonce.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = InitializerStats::SPACE,
+        seeds = [b"initializer_stats".as_ref(), initializer.key().as_ref()],
+        bump,
+    )]
+    pub initializer_stats: Account<'info, InitializerStats>,
+    /// The initializer's escrow list, if they keep one
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), initializer.key().as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// The taker's escrow list, if they keep one
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), taker.key().as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(nonce: u64, initializer_nft_count: u8, taker_nft_count: u8)]
+pub struct InitializeOpen<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// Pays the offer account's rent and gets it back when the offer closes
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + EscrowAccount::space(initializer_nft_count, taker_nft_count),
+        seeds = [
+            b"offer".as_ref(),
+            initializer.key().as_ref(),
+            nonce.to_le_bytes().as_ref(),
+        ],
+        bump,
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = InitializerStats::SPACE,
+        seeds = [b"initializer_stats".as_ref(), initializer.key().as_ref()],
+        bump,
+    )]
+    pub initializer_stats: Account<'info, InitializerStats>,
+    /// The initializer's escrow list, if they keep one
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), initializer.key().as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub system_program: Program<'info, System>,
+    /// The token program every NFT in this offer has to move through
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimOffer<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = escrow_account.is_open_offer @ EscrowError::NotAnOpenOffer
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct Deposit<'info> {
+    /// The depositing party, or its registered delegate. A delegate must also be approved
+    /// as the SPL token delegate on `token_account` to move the NFT. This may be a PDA
+    /// signing through CPI, since it never has to fund anything.
+    pub depositor: Signer<'info>,
+    /// Pays for the vault if it does not exist yet, and gets that rent back when it closes
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// Metaplex metadata for `mint`, required when the depositor's side has an expected collection,
+    /// a collection whitelist or an expected symbol
+    pub metadata_account: Option<Account<'info, MetadataAccount>>,
+    #[account(
+        mut,
+        constraint = token_account.owner == if is_initializer {
+            escrow_account.initializer
+        } else {
+            escrow_account.taker
+        } @ EscrowError::InvalidTokenAccount,
+        constraint = token_account.mint == mint.key() @ EscrowError::InvalidNftMint
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_account,
+        associated_token::token_program = token_program
+    )]
+    pub vault_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Same accounts as `Deposit` for a vault that already exists, so nothing needs paying for
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct DepositToVault<'info> {
+    /// The depositing party, or its registered delegate. Recorded as the vault's funder.
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// Metaplex metadata for `mint`, required when the depositor's side has an expected collection,
+    /// a collection whitelist or an expected symbol
+    pub metadata_account: Option<Account<'info, MetadataAccount>>,
+    #[account(
+        mut,
+        constraint = token_account.owner == if is_initializer {
+            escrow_account.initializer
+        } else {
+            escrow_account.taker
+        } @ EscrowError::InvalidTokenAccount,
+        constraint = token_account.mint == mint.key() @ EscrowError::InvalidNftMint
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+    /// The escrow's associated token account for `mint`, created beforehand
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_account,
+        associated_token::token_program = token_program
+    )]
+    pub vault_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Accounts for `deposit_compressed`, plus Bubblegum's. The leaf's proof path follows in
+/// `remaining_accounts`.
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct DepositCompressed<'info> {
+    /// The depositing party, or its registered delegate acting as the leaf's delegate
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: The party whose side this is; Bubblegum checks it owns the leaf
+    #[account(
+        address = if is_initializer {
+            escrow_account.initializer
+        } else {
+            escrow_account.taker
+        } @ EscrowError::InvalidDepositor
+    )]
+    pub leaf_owner: UncheckedAccount<'info>,
+    /// CHECK: The leaf's current delegate, the owner itself when none is set; checked by Bubblegum
+    pub leaf_delegate: UncheckedAccount<'info>,
+    /// CHECK: Bubblegum's tree config PDA for `merkle_tree`; checked by Bubblegum
+    pub tree_config: UncheckedAccount<'info>,
+    /// CHECK: The cNFT's merkle tree; Bubblegum checks the leaf's proof against it
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: The SPL Noop program Bubblegum logs leaf changes through
+    #[account(address = SPL_NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+    /// CHECK: The SPL Account Compression program
+    #[account(address = SPL_ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+    /// CHECK: The Bubblegum program
+    #[account(address = BUBBLEGUM_PROGRAM_ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct WithdrawCompressed<'info> {
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (is_initializer && depositor.key() == escrow_account.initializer) ||
+                   (!is_initializer && depositor.key() == escrow_account.taker) @ EscrowError::InvalidDepositor
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: Bubblegum's tree config PDA for `merkle_tree`; checked by Bubblegum
+    pub tree_config: UncheckedAccount<'info>,
+    /// CHECK: The cNFT's merkle tree; Bubblegum checks the leaf's proof against it
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: The SPL Noop program Bubblegum logs leaf changes through
+    #[account(address = SPL_NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+    /// CHECK: The SPL Account Compression program
+    #[account(address = SPL_ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+    /// CHECK: The Bubblegum program
+    #[account(address = BUBBLEGUM_PROGRAM_ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Same accounts as `Complete`, with Bubblegum's in place of the mint and its token accounts
+#[derive(Accounts)]
+pub struct CompleteCompressed<'info> {
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (caller.key() == escrow_account.initializer ||
+                   caller.key() == escrow_account.taker) @ EscrowError::InvalidCaller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: The caller's delivery address, required when they set one
+    pub delivery_wallet: Option<UncheckedAccount<'info>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: Bubblegum's tree config PDA for `merkle_tree`; checked by Bubblegum
+    pub tree_config: UncheckedAccount<'info>,
+    /// CHECK: The cNFT's merkle tree; Bubblegum checks the leaf's proof against it
+    #[account(mut)]
+    pub merkle_tree: UncheckedAccount<'info>,
+    /// CHECK: The SPL Noop program Bubblegum logs leaf changes through
+    #[account(address = SPL_NOOP_PROGRAM_ID)]
+    pub log_wrapper: UncheckedAccount<'info>,
+    /// CHECK: The SPL Account Compression program
+    #[account(address = SPL_ACCOUNT_COMPRESSION_PROGRAM_ID)]
+    pub compression_program: UncheckedAccount<'info>,
+    /// CHECK: The Bubblegum program
+    #[account(address = BUBBLEGUM_PROGRAM_ID)]
+    pub bubblegum_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Same accounts as `Deposit`, minus the vault and anything only needed to create it
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct ValidateDeposit<'info> {
+    /// CHECK: Compared against the party and its delegate; a preview doesn't need a signature
+    pub depositor: UncheckedAccount<'info>,
+    #[account(
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub metadata_account: Option<Account<'info, MetadataAccount>>,
+    #[account(
+        constraint = token_account.owner == if is_initializer {
+            escrow_account.initializer
+        } else {
+            escrow_account.taker
+        } @ EscrowError::InvalidTokenAccount,
+        constraint = token_account.mint == mint.key() @ EscrowError::InvalidNftMint
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, start_index: u8, count: u8)]
+pub struct DepositAll<'info> {
+    /// The depositing party, or its registered delegate
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositSol<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = taker.key() == escrow_account.taker @ EscrowError::InvalidDepositor
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositTokens<'info> {
+    /// The taker, or its registered delegate
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(address = escrow_account.token_mint @ EscrowError::InvalidTokenAccount)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = token_account.owner == escrow_account.taker @ EscrowError::InvalidTokenAccount,
+        constraint = token_account.mint == mint.key() @ EscrowError::InvalidTokenAccount
+    )]
+    pub token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = depositor,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_account,
+        associated_token::token_program = token_program
+    )]
+    pub vault_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Reconcile<'info> {
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (caller.key() == escrow_account.initializer ||
+                   caller.key() == escrow_account.taker) @ EscrowError::InvalidCaller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ConfirmReady<'info> {
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (caller.key() == escrow_account.initializer ||
+                   caller.key() == escrow_account.taker) @ EscrowError::InvalidCaller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct Complete<'info> {
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (caller.key() == escrow_account.initializer || 
+                   caller.key() == escrow_account.taker) @ EscrowError::InvalidCaller,
+        constraint = escrow_account.initializer_deposited && escrow_account.taker_deposited @ EscrowError::DepositsIncomplete
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: This is whoever funded the vault being emptied; validated against the escrow's records
+    #[account(mut)]
+    pub vault_funder: UncheckedAccount<'info>,
+    /// CHECK: This is the party receiving the NFT: the initializer when collecting a taker NFT,
+    /// the taker when collecting an initializer NFT. Always the caller, so it can pay for its
+    /// token account when one has to be created.
+    #[account(
+        mut,
+        constraint = recipient.key() == if is_initializer {
+            escrow_account.initializer
+        } else {
+            escrow_account.taker
+        } @ EscrowError::InvalidRecipient
+    )]
+    pub recipient: UncheckedAccount<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    /// Metaplex metadata for `mint`, required when collecting an initializer NFT from an
+    /// escrow that enforces royalties. Its creators follow in `remaining_accounts`.
+    pub metadata_account: Option<Account<'info, MetadataAccount>>,
+    /// CHECK: The escrow's vault for `mint`. Loaded in the handler only after the collected
+    /// flags are checked, since a collected NFT's vault has already been closed.
+    #[account(mut)]
+    pub vault_account: UncheckedAccount<'info>,
+    /// CHECK: The recipient's ATA for `mint`, created if it does not exist yet. Derivation and
+    /// contents are validated in the handler.
+    #[account(mut)]
+    pub recipient_token_account: UncheckedAccount<'info>,
+    /// CHECK: The recipient's delivery address, required when they set one; its token account
+    /// is derived from it and validated in the handler
+    pub delivery_wallet: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, start_index: u8, count: u8)]
+pub struct CompleteAll<'info> {
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (caller.key() == escrow_account.initializer || 
+                   caller.key() == escrow_account.taker) @ EscrowError::InvalidCaller,
+        constraint = escrow_account.initializer_deposited && escrow_account.taker_deposited @ EscrowError::DepositsIncomplete
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: This is the collecting party, which is always the caller. Their NFTs go to the
+    /// side's delivery address instead when one is set.
+    #[account(
+        mut,
+        constraint = recipient.key() == if is_initializer {
+            escrow_account.initializer
+        } else {
+            escrow_account.taker
+        } @ EscrowError::InvalidRecipient
+    )]
+    pub recipient: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+/// The lifecycle half of a collection: everything `finalize` needs to release the payments
+/// and close the escrow
+#[derive(Accounts)]
+pub struct Finalize<'info> {
+    /// Anyone can finalize; the escrow's own state decides whether it is done
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is the initializer who receives the taker's SOL and token payments
+    #[account(mut, address = escrow_account.initializer)]
+    pub initializer: UncheckedAccount<'info>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// CHECK: This is the protocol fee recipient recorded at initialization
+    #[account(mut, address = escrow_account.fee_recipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(is_initializer: bool, nft_index: u8)]
+pub struct WithdrawDeposited<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (is_initializer && depositor.key() == escrow_account.initializer) ||
+                   (!is_initializer && depositor.key() == escrow_account.taker) @ EscrowError::InvalidDepositor
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is whoever funded the vault being emptied; validated against the escrow's records
+    #[account(mut)]
+    pub vault_funder: UncheckedAccount<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = escrow_account,
+        associated_token::token_program = token_program
+    )]
+    pub vault_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = depositor,
+        associated_token::mint = mint,
+        associated_token::authority = depositor,
+        associated_token::token_program = token_program
+    )]
+    pub depositor_token_account: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Cancel<'info> {
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = initializer.key() == escrow_account.initializer @ EscrowError::InvalidCanceller,
+        constraint = !escrow_account.initializer_deposited && !escrow_account.taker_deposited @ EscrowError::CannotCancelAfterDeposit,
+        constraint = !escrow_account.initializer_nft_deposited.contains(&true)
+            && !escrow_account.taker_nft_deposited.contains(&true) @ EscrowError::CannotCancelAfterDeposit,
+        close = rent_payer
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// CHECK: This is the taker who will receive any SOL payment refund. Unclaimed open
+    /// offers have no taker, so it is only required once SOL has been deposited.
+    #[account(mut, address = escrow_account.taker)]
+    pub taker: Option<UncheckedAccount<'info>>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    /// Required when a token leg was deposited or stray vaults are passed for cleanup.
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct RefundExpired<'info> {
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        close = rent_payer
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// CHECK: This is the taker who will receive any SOL payment refund. Unclaimed open
+    /// offers have no taker, so it is only required once SOL has been deposited.
+    #[account(mut, address = escrow_account.taker)]
+    pub taker: Option<UncheckedAccount<'info>>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimUnmatched<'info> {
+    /// The party reclaiming their side; a claiming taker also receives any SOL refund
+    #[account(mut)]
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct ArbiterSettle<'info> {
+    pub arbiter: Signer<'info>,
+    #[account(mut)]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is the initializer, who receives taker NFTs and payments on completion
+    #[account(mut, address = escrow_account.initializer)]
+    pub initializer: UncheckedAccount<'info>,
+    /// CHECK: This is the taker, who receives initializer NFTs on completion or the SOL back on refund
+    #[account(mut, address = escrow_account.taker)]
+    pub taker: UncheckedAccount<'info>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// CHECK: This is the protocol fee recipient recorded at initialization
+    #[account(mut, address = escrow_account.fee_recipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Settle<'info> {
+    /// Anyone can settle; the escrow's own state decides whether it is ready
+    pub caller: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: This is the initializer, who receives the taker's NFTs and payments
+    #[account(mut, address = escrow_account.initializer)]
+    pub initializer: UncheckedAccount<'info>,
+    /// CHECK: This is the taker, who receives the initializer's NFTs
+    #[account(mut, address = escrow_account.taker)]
+    pub taker: UncheckedAccount<'info>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// CHECK: This is the protocol fee recipient recorded at initialization
+    #[account(mut, address = escrow_account.fee_recipient)]
+    pub fee_recipient: UncheckedAccount<'info>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Reap<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        close = rent_payer
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SetDeliveryAddress<'info> {
+    /// The initializer or taker, setting the address for their own side
+    pub party: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = (party.key() == escrow_account.initializer ||
+                   party.key() == escrow_account.taker) @ EscrowError::InvalidCaller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Approve<'info> {
+    pub approver: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = escrow_account.approver == Some(approver.key()) @ EscrowError::NotApprover
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Reject<'info> {
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = taker.key() == escrow_account.taker @ EscrowError::OnlyTakerCanReject,
+        close = rent_payer
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back on close
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeCounter<'info> {
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = taker.key() == escrow_account.taker @ EscrowError::OnlyTakerCanCounter
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptCounter<'info> {
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = initializer.key() == escrow_account.initializer @ EscrowError::InvalidCanceller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(new_initializer: Pubkey)]
+pub struct TransferInitializer<'info> {
+    /// The current initializer, who also pays for the new initializer's counter if needed
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = initializer.key() == escrow_account.initializer @ EscrowError::InvalidCanceller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(seeds = [b"config".as_ref()], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// Both initializers' active escrow counters; required if this escrow is counted
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), initializer.key().as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    #[account(
+        init_if_needed,
         payer = initializer,
-        space = 8 + EscrowAccount::space(),
-        seeds = [
-            b"escrow".as_ref(),
-            initializer.key().as_ref(),
-            taker.key().as_ref(),
-        ],
+        space = InitializerStats::SPACE,
+        seeds = [b"initializer_stats".as_ref(), new_initializer.as_ref()],
         bump,
     )]
+    pub new_initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// Both initializers' escrow lists, if they keep them
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), initializer.key().as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), new_initializer.as_ref()],
+        bump = new_initializer_escrow_list.bump
+    )]
+    pub new_initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    /// The new initializer, whose signature is needed to add the escrow to their list
+    #[account(address = new_initializer)]
+    pub new_initializer_account: Option<Signer<'info>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetTaker<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = initializer.key() == escrow_account.initializer @ EscrowError::InvalidCanceller
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct ExtendTimeout<'info> {
+    pub initializer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = initializer.key() == escrow_account.initializer @ EscrowError::InvalidCanceller
+    )]
     pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct Migrate<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: May still hold an old layout that `Account` cannot deserialize; the handler
+    /// checks the discriminator and decodes it by hand
+    #[account(mut, owner = crate::ID)]
+    pub escrow_account: UncheckedAccount<'info>,
     pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
 }
 
-#[derive(Accounts)]
-#[instruction(is_initializer: bool, nft_index: u8)]
-pub struct Deposit<'info> {
-    #[account(mut)]
-    pub depositor: Signer<'info>,
-    #[account(
-        mut,
-        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
-        constraint = (is_initializer && depositor.key() == escrow_account.initializer) ||
-                   (!is_initializer && depositor.key() == escrow_account.taker) @ EscrowError::InvalidDepositor
-    )]
-    pub escrow_account: Account<'info, EscrowAccount>,
-    pub mint: Account<'info, Mint>,
-    #[account(
-        mut,
-        constraint = token_account.owner == depositor.key() @ EscrowError::InvalidTokenAccount,
-        constraint = token_account.mint == mint.key() @ EscrowError::InvalidNftMint
-    )]
-    pub token_account: Account<'info, TokenAccount>,
-    #[account(
-        init_if_needed,
-        payer = depositor,
-        associated_token::mint = mint,
-        associated_token::authority = escrow_account
-    )]
-    pub vault_account: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+#[derive(Accounts)]
+pub struct FundRent<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: Only receives lamports; may still hold an old layout awaiting migration
+    #[account(mut, owner = crate::ID)]
+    pub escrow_account: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[cfg(feature = "test")]
+#[derive(Accounts)]
+pub struct WarpCreatedAt<'info> {
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct GetStatus<'info> {
+    pub escrow_account: Account<'info, EscrowAccount>,
+}
+
+#[derive(Accounts)]
+pub struct CancelMutual<'info> {
+    #[account(
+        mut,
+        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
+        constraint = initializer.key() == escrow_account.initializer @ EscrowError::BothPartiesMustSign,
+        constraint = taker.key() == escrow_account.taker @ EscrowError::BothPartiesMustSign,
+        close = rent_payer
+    )]
+    pub escrow_account: Account<'info, EscrowAccount>,
+    #[account(mut)]
+    pub initializer: Signer<'info>,
+    /// CHECK: This is whoever paid for the escrow account; it receives the rent back
+    #[account(mut, address = escrow_account.rent_payer)]
+    pub rent_payer: UncheckedAccount<'info>,
+    /// The initializer's active escrow counter; required if this escrow is counted in it
+    #[account(
+        mut,
+        seeds = [b"initializer_stats".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_stats.bump
+    )]
+    pub initializer_stats: Option<Account<'info, InitializerStats>>,
+    /// The parties' escrow lists; any that are passed have this escrow removed
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.initializer.as_ref()],
+        bump = initializer_escrow_list.bump
+    )]
+    pub initializer_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(
+        mut,
+        seeds = [b"escrow_list".as_ref(), escrow_account.taker.as_ref()],
+        bump = taker_escrow_list.bump
+    )]
+    pub taker_escrow_list: Option<Account<'info, UserEscrowList>>,
+    #[account(mut)]
+    pub taker: Signer<'info>,
+    /// The escrowed token leg's mint, vault, the account receiving it and whoever funded the
+    /// vault. Only required once a token leg has been deposited.
+    pub token_leg_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(mut)]
+    pub token_leg_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_leg_destination: Option<InterfaceAccount<'info, TokenAccount>>,
+    /// CHECK: Validated against the escrow's recorded token vault funder
+    #[account(mut)]
+    pub token_leg_vault_funder: Option<UncheckedAccount<'info>>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EscrowStatus {
+    Created,
+    PartiallyFunded,
+    Funded,
+    Completing,
+    Completed,
+    Cancelled,
+}
+
+/// How an arbiter resolves a disputed escrow.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArbiterDecision {
+    /// Deliver every NFT to its counterparty and release the taker's payment, as if both
+    /// parties had collected.
+    Complete,
+    /// Return every deposit to the party that made it.
+    Refund,
+}
+
+/// The Bubblegum leaf a compressed NFT transfer acts on, as a DAS `getAssetProof` and
+/// `getAsset` report it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CompressedLeaf {
+    pub root: [u8; 32],
+    pub data_hash: [u8; 32],
+    pub creator_hash: [u8; 32],
+    pub nonce: u64,
+    pub index: u32,
+}
+
+/// The flags derived from the per-NFT arrays, as rewritten by `reconcile`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AggregateFlags {
+    pub status: EscrowStatus,
+    pub initializer_deposited: bool,
+    pub taker_deposited: bool,
+    pub initializer_collected: bool,
+    pub taker_collected: bool,
+}
+
+/// Deposit and collection progress for each side, returned by `get_status`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct EscrowProgress {
+    pub status: EscrowStatus,
+    pub initializer_nfts_deposited: u8,
+    pub initializer_nfts_remaining_to_deposit: u8,
+    pub initializer_nfts_collected: u8,
+    pub initializer_nfts_remaining_to_collect: u8,
+    pub taker_nfts_deposited: u8,
+    pub taker_nfts_remaining_to_deposit: u8,
+    pub taker_nfts_collected: u8,
+    pub taker_nfts_remaining_to_collect: u8,
+    pub sol_deposited: bool,
+    pub token_deposited: bool,
+}
+
+/// One NFT slot's state, returned by `check_nft_state`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct NftState {
+    pub deposited: bool,
+    pub collected: bool,
+}
+
+/// Program-wide settings. Pausing blocks new escrows, deposits and completions; cancellations,
+/// refunds and withdrawals stay available so funds are never trapped.
+#[account]
+pub struct Config {
+    pub admin: Pubkey,
+    pub paused: bool,
+    pub bump: u8,
+    /// Paid to keepers out of the rent of each abandoned escrow they reap
+    pub crank_fee_lamports: u64,
+    /// Most escrows one initializer may have open at once; 0 means no cap
+    pub max_active_escrows: u16,
+    /// May pause and unpause the program alongside the admin, but change nothing else
+    pub pauser: Pubkey,
+    /// Token programs new escrows may be created with, up to `MAX_ALLOWED_TOKEN_PROGRAMS`
+    pub allowed_token_programs: Vec<Pubkey>,
+    /// May report completion failures to the circuit breaker, alongside the admin
+    pub failure_reporter: Pubkey,
+    /// Reported failures within one window that pause the program; 0 disables the breaker
+    pub failure_threshold: u16,
+    pub failure_window_seconds: i64,
+    /// When the current counting window opened, and how many failures it has seen
+    pub failure_window_start: i64,
+    pub failure_count: u16,
+}
+
+impl Config {
+    pub const SPACE: usize = 8 +  // discriminator
+        32 + // admin
+        1 +  // paused
+        1 +  // bump
+        8 +  // crank_fee_lamports
+        2 +  // max_active_escrows
+        32 + // pauser
+        4 + 32 * MAX_ALLOWED_TOKEN_PROGRAMS + // allowed_token_programs
+        32 + // failure_reporter
+        2 +  // failure_threshold
+        8 +  // failure_window_seconds
+        8 +  // failure_window_start
+        2; // failure_count
+
+    pub fn check_token_program(&self, token_program: &Pubkey) -> Result<()> {
+        require!(
+            self.allowed_token_programs.contains(token_program),
+            EscrowError::TokenProgramNotAllowed
+        );
+        Ok(())
+    }
+}
+
+/// Counts an initializer's escrows that have not closed yet, so `Config::max_active_escrows`
+/// can be enforced
+#[account]
+pub struct InitializerStats {
+    pub initializer: Pubkey,
+    pub active_escrows: u16,
+    pub bump: u8,
+}
+
+impl InitializerStats {
+    pub const SPACE: usize = 8 +  // discriminator
+        32 + // initializer
+        2 +  // active_escrows
+        1; // bump
+}
+
+/// Every open escrow a user takes part in, so clients can find them without scanning the
+/// program's accounts. Grows as escrows are added, up to `MAX_LISTED_ESCROWS`.
+#[account]
+pub struct UserEscrowList {
+    pub owner: Pubkey,
+    pub bump: u8,
+    pub escrows: Vec<Pubkey>,
+}
+
+impl UserEscrowList {
+    pub fn space(escrow_count: usize) -> usize {
+        32 + // owner
+        1 +  // bump
+        4 + (32 * escrow_count) // escrows
+    }
+}
+
+#[account]
+pub struct EscrowAccount {
+    /// Always the first field so it sits right after the discriminator in every layout
+    pub version: u8,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub initializer_nft_count: u8,
+    pub taker_nft_count: u8,
+    pub initializer_nft_mints: Vec<Pubkey>,
+    pub taker_nft_mints: Vec<Pubkey>,
+    pub initializer_nft_deposited: Vec<bool>,
+    pub taker_nft_deposited: Vec<bool>,
+    pub initializer_nft_collected: Vec<bool>,
+    pub taker_nft_collected: Vec<bool>,
+    pub initializer_deposited: bool,
+    pub taker_deposited: bool,
+    pub initializer_collected: bool,
+    pub taker_collected: bool,
+    pub is_initialized: bool,
+    pub bump: u8,
+    pub created_at: i64,
+    pub timeout_in_seconds: i64,
+    pub sol_amount: u64,
+    pub sol_deposited: bool,
+    pub maker_fee_bps: u16,
+    pub fee_recipient: Pubkey,
+    pub initializer_expected_collection: Option<Pubkey>,
+    pub taker_expected_collection: Option<Pubkey>,
+    pub status: EscrowStatus,
+    pub timeout_extension_seconds: i64,
+    /// Who paid for each NFT vault. Every account the escrow closes hands its rent back to
+    /// the party that funded it: NFT vaults to these funders, the token leg's vault to
+    /// `token_vault_funder` and the escrow itself to `rent_payer`.
+    pub initializer_vault_funders: Vec<Pubkey>,
+    pub taker_vault_funders: Vec<Pubkey>,
+    pub nonce: u64,
+    pub is_open_offer: bool,
+    pub pending_acceptance: bool,
+    pub initializer_ready: bool,
+    pub taker_ready: bool,
+    pub uses_legacy_seeds: bool,
+    pub initializer_delegate: Option<Pubkey>,
+    pub taker_delegate: Option<Pubkey>,
+    pub allowed_taker_collections: Vec<Pubkey>,
+    pub token_mint: Pubkey,
+    pub token_amount: u64,
+    pub token_deposited: bool,
+    pub token_vault_funder: Pubkey,
+    pub in_progress: bool,
+    pub initializer_expected_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+    pub taker_expected_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+    pub last_collector: Pubkey,
+    pub deposit_deadline: i64,
+    pub rent_payer: Pubkey,
+    pub taker_fee_bps: u16,
+    pub token_program_id: Pubkey,
+    pub initializer_nft_deposited_at: Vec<i64>,
+    pub taker_nft_deposited_at: Vec<i64>,
+    pub arbiter: Option<Pubkey>,
+    pub sol_deposit_deadline: i64,
+    pub enforce_royalties: bool,
+    pub royalties_paid: u64,
+    /// Whether this escrow is counted in its initializer's `InitializerStats`
+    pub counted_as_active: bool,
+    /// When set, the timeout runs from `last_activity_at` instead of `created_at`
+    pub sliding_timeout: bool,
+    /// When the last deposit of any asset landed, or `created_at` before the first one
+    pub last_activity_at: i64,
+    /// The initializer this escrow's PDA was derived from. Stays put when
+    /// `transfer_initializer` hands the position to someone else.
+    pub seed_initializer: Pubkey,
+    /// When set, `complete` refuses an NFT whose metadata changed since it was deposited
+    pub block_on_metadata_change: bool,
+    /// Fingerprint of each NFT's metadata taken by `deposit`; all zeroes when none was taken
+    pub initializer_metadata_hashes: Vec<[u8; 32]>,
+    pub taker_metadata_hashes: Vec<[u8; 32]>,
+    /// Extra seconds past the timeout during which only the parties themselves may unwind
+    pub keeper_grace_seconds: i64,
+    /// Opaque label set by the initializer, e.g. an off-chain order id
+    pub memo: [u8; 32],
+    /// Which slots hold a compressed NFT, whose listed mint is its Bubblegum asset id
+    pub initializer_nft_compressed: Vec<bool>,
+    pub taker_nft_compressed: Vec<bool>,
+    /// Reviews the escrow before deposits open; `None` leaves it approved from the start
+    pub approver: Option<Pubkey>,
+    pub approved: bool,
+    /// How many tokens of its mint each slot holds; 1 for an ordinary NFT
+    pub initializer_nft_amounts: Vec<u64>,
+    pub taker_nft_amounts: Vec<u64>,
+    /// Wallet each side's incoming NFTs are delivered to instead of the party's own; fixed
+    /// once anything has been deposited
+    pub initializer_delivery_address: Option<Pubkey>,
+    pub taker_delivery_address: Option<Pubkey>,
+    /// Whether each party's escrow list holds this escrow, so closing it must pass that list
+    pub listed_initializer: bool,
+    pub listed_taker: bool,
+}
+
+impl EscrowAccount {
+    pub fn space(initializer_nft_count: u8, taker_nft_count: u8) -> usize {
+        let initializer_nft_count = initializer_nft_count as usize;
+        let taker_nft_count = taker_nft_count as usize;
+
+        8 +  // discriminator
+        1 +  // version
+        32 + // initializer
+        32 + // taker
+        1 +  // initializer_nft_count
+        1 +  // taker_nft_count
+        4 + (32 * initializer_nft_count) + // initializer_nft_mints
+        4 + (32 * taker_nft_count) + // taker_nft_mints
+        4 + initializer_nft_count + // initializer_nft_deposited
+        4 + taker_nft_count + // taker_nft_deposited
+        4 + initializer_nft_count + // initializer_nft_collected
+        4 + taker_nft_count + // taker_nft_collected
+        1 +  // initializer_deposited
+        1 +  // taker_deposited
+        1 +  // initializer_collected
+        1 +  // taker_collected
+        1 +  // is_initialized
+        1 +  // bump
+        8 +  // created_at
+        8 +  // timeout_in_seconds
+        8 +  // sol_amount
+        1 +  // sol_deposited
+        2 +  // maker_fee_bps
+        32 + // fee_recipient
+        (1 + 32) + // initializer_expected_collection
+        (1 + 32) + // taker_expected_collection
+        1 +  // status
+        8 +  // timeout_extension_seconds
+        4 + (32 * initializer_nft_count) + // initializer_vault_funders
+        4 + (32 * taker_nft_count) + // taker_vault_funders
+        8 +  // nonce
+        1 +  // is_open_offer
+        1 +  // pending_acceptance
+        1 +  // initializer_ready
+        1 +  // taker_ready
+        1 +  // uses_legacy_seeds
+        (1 + 32) + // initializer_delegate
+        (1 + 32) + // taker_delegate
+        4 + (32 * MAX_ALLOWED_TAKER_COLLECTIONS) + // allowed_taker_collections
+        32 + // token_mint
+        8 +  // token_amount
+        1 +  // token_deposited
+        32 + // token_vault_funder
+        1 +  // in_progress
+        (1 + MAX_SYMBOL_LENGTH) + // initializer_expected_symbol
+        (1 + MAX_SYMBOL_LENGTH) + // taker_expected_symbol
+        32 + // last_collector
+        8 +  // deposit_deadline
+        32 + // rent_payer
+        2 +  // taker_fee_bps
+        32 + // token_program_id
+        4 + (8 * initializer_nft_count) + // initializer_nft_deposited_at
+        4 + (8 * taker_nft_count) + // taker_nft_deposited_at
+        (1 + 32) + // arbiter
+        8 +  // sol_deposit_deadline
+        1 +  // enforce_royalties
+        8 +  // royalties_paid
+        1 +  // counted_as_active
+        1 +  // sliding_timeout
+        8 +  // last_activity_at
+        32 + // seed_initializer
+        1 +  // block_on_metadata_change
+        4 + (32 * initializer_nft_count) + // initializer_metadata_hashes
+        4 + (32 * taker_nft_count) + // taker_metadata_hashes
+        8 +  // keeper_grace_seconds
+        32 + // memo
+        4 + initializer_nft_count + // initializer_nft_compressed
+        4 + taker_nft_count + // taker_nft_compressed
+        (1 + 32) + // approver
+        1 + // approved
+        4 + (8 * initializer_nft_count) + // initializer_nft_amounts
+        4 + (8 * taker_nft_count) + // taker_nft_amounts
+        (1 + 32) + // initializer_delivery_address
+        (1 + 32) + // taker_delivery_address
+        1 + // listed_initializer
+        1 // listed_taker
+    }
+
+    /// Calls `f` with the PDA signer seeds for this escrow. Open offers are seeded by
+    /// initializer and nonce, directed escrows by initializer, taker and nonce, always using
+    /// the original initializer. Escrows migrated from the original layout keep their
+    /// nonce-less seeds.
+    pub fn with_signer_seeds<T>(&self, f: impl FnOnce(&[&[&[u8]]]) -> T) -> T {
+        let bump = [self.bump];
+        let nonce = self.nonce.to_le_bytes();
+        if self.is_open_offer {
+            let seeds: &[&[u8]] = &[b"offer", self.seed_initializer.as_ref(), &nonce, &bump];
+            f(&[seeds])
+        } else if self.uses_legacy_seeds {
+            let seeds: &[&[u8]] = &[b"escrow", self.seed_initializer.as_ref(), self.taker.as_ref(), &bump];
+            f(&[seeds])
+        } else {
+            let seeds: &[&[u8]] = &[
+                b"escrow",
+                self.seed_initializer.as_ref(),
+                self.taker.as_ref(),
+                &nonce,
+                &bump,
+            ];
+            f(&[seeds])
+        }
+    }
+
+    /// Indices of NFTs on one side that are deposited but not yet collected, i.e. still in
+    /// their vaults.
+    pub fn unmatched_nfts(&self, is_initializer: bool) -> Vec<u8> {
+        let (deposited, collected) = if is_initializer {
+            (&self.initializer_nft_deposited, &self.initializer_nft_collected)
+        } else {
+            (&self.taker_nft_deposited, &self.taker_nft_collected)
+        };
+        (0..deposited.len())
+            .filter(|&i| deposited[i] && !collected[i])
+            .map(|i| i as u8)
+            .collect()
+    }
+
+    /// Indices of NFTs on one side that have not been deposited yet.
+    pub fn pending_deposits(&self, is_initializer: bool) -> Vec<u8> {
+        let nft_deposited = if is_initializer {
+            &self.initializer_nft_deposited
+        } else {
+            &self.taker_nft_deposited
+        };
+        (0..nft_deposited.len())
+            .filter(|&i| !nft_deposited[i])
+            .map(|i| i as u8)
+            .collect()
+    }
+
+    /// Unix timestamp after which the escrow counts as timed out. A sliding timeout restarts
+    /// with every deposit, so parties still actively depositing aren't cut off.
+    pub fn expires_at(&self) -> Result<i64> {
+        let start = if self.sliding_timeout { self.last_activity_at } else { self.created_at };
+        start
+            .checked_add(self.timeout_in_seconds)
+            .ok_or_else(|| error!(EscrowError::TimeoutOverflow))
+    }
+
+    /// Checks that `caller` may unwind the escrow at `now`, which is already past the timeout.
+    /// The parties may act right away; anyone else waits out `keeper_grace_seconds` first.
+    pub fn check_keeper_grace(&self, caller: &Pubkey, now: i64) -> Result<()> {
+        if *caller == self.initializer || *caller == self.taker {
+            return Ok(());
+        }
+        let keepers_from = self
+            .expires_at()?
+            .checked_add(self.keeper_grace_seconds)
+            .ok_or_else(|| error!(EscrowError::TimeoutOverflow))?;
+        require!(now > keepers_from, EscrowError::KeeperGracePeriodActive);
+        Ok(())
+    }
+
+    /// Seconds left at `now` until the timeout lets `cancel` and `refund_expired` run,
+    /// or 0 once it has passed.
+    pub fn seconds_until_expiry(&self, now: i64) -> Result<i64> {
+        Ok(self.expires_at()?.saturating_sub(now).max(0))
+    }
+
+    pub fn deposited_count(&self, is_initializer: bool) -> u8 {
+        let nft_deposited = if is_initializer {
+            &self.initializer_nft_deposited
+        } else {
+            &self.taker_nft_deposited
+        };
+        nft_deposited.iter().filter(|&&x| x).count() as u8
+    }
+
+    pub fn collected_count(&self, is_initializer: bool) -> u8 {
+        let nft_collected = if is_initializer {
+            &self.initializer_nft_collected
+        } else {
+            &self.taker_nft_collected
+        };
+        nft_collected.iter().filter(|&&x| x).count() as u8
+    }
+
+    pub fn progress(&self) -> EscrowProgress {
+        let initializer_nfts_deposited = self.deposited_count(true);
+        let initializer_nfts_collected = self.collected_count(true);
+        let taker_nfts_deposited = self.deposited_count(false);
+        let taker_nfts_collected = self.collected_count(false);
+
+        EscrowProgress {
+            status: self.status,
+            initializer_nfts_deposited,
+            initializer_nfts_remaining_to_deposit: self.initializer_nft_count - initializer_nfts_deposited,
+            initializer_nfts_collected,
+            initializer_nfts_remaining_to_collect: self.initializer_nft_count - initializer_nfts_collected,
+            taker_nfts_deposited,
+            taker_nfts_remaining_to_deposit: self.taker_nft_count - taker_nfts_deposited,
+            taker_nfts_collected,
+            taker_nfts_remaining_to_collect: self.taker_nft_count - taker_nfts_collected,
+            sol_deposited: self.sol_deposited,
+            token_deposited: self.token_deposited,
+        }
+    }
+
+    /// Records `funder` as the payer of the vault at `nft_index` unless one is already known.
+    pub fn record_vault_funder(&mut self, is_initializer: bool, nft_index: u8, funder: Pubkey) {
+        let funders = if is_initializer {
+            &mut self.initializer_vault_funders
+        } else {
+            &mut self.taker_vault_funders
+        };
+        if funders[nft_index as usize] == Pubkey::default() {
+            funders[nft_index as usize] = funder;
+        }
+    }
+
+    /// Clears and returns the recorded funder of the vault at `nft_index`, once that vault is
+    /// being closed ahead of the escrow.
+    pub fn take_vault_funder(&mut self, is_initializer: bool, nft_index: u8) -> Pubkey {
+        let funders = if is_initializer {
+            &mut self.initializer_vault_funders
+        } else {
+            &mut self.taker_vault_funders
+        };
+        std::mem::take(&mut funders[nft_index as usize])
+    }
+
+    /// Records when the NFT at `nft_index` landed in its vault; 0 means it is not deposited.
+    pub fn record_deposit_time(&mut self, is_initializer: bool, nft_index: u8, at: i64) {
+        if is_initializer {
+            self.initializer_nft_deposited_at[nft_index as usize] = at;
+        } else {
+            self.taker_nft_deposited_at[nft_index as usize] = at;
+        }
+    }
+
+    /// Records the metadata fingerprint of the NFT at `nft_index`; all zeroes clears it.
+    pub fn record_metadata_hash(&mut self, is_initializer: bool, nft_index: u8, hash: [u8; 32]) {
+        if is_initializer {
+            self.initializer_metadata_hashes[nft_index as usize] = hash;
+        } else {
+            self.taker_metadata_hashes[nft_index as usize] = hash;
+        }
+    }
+
+    /// Marks whether the NFT at `nft_index` was deposited as a compressed NFT.
+    pub fn record_compressed(&mut self, is_initializer: bool, nft_index: u8, compressed: bool) {
+        if is_initializer {
+            self.initializer_nft_compressed[nft_index as usize] = compressed;
+        } else {
+            self.taker_nft_compressed[nft_index as usize] = compressed;
+        }
+    }
+
+    /// How many tokens the slot at `nft_index` on one side holds once deposited.
+    pub fn nft_amount(&self, is_initializer: bool, nft_index: u8) -> u64 {
+        let amounts = if is_initializer {
+            &self.initializer_nft_amounts
+        } else {
+            &self.taker_nft_amounts
+        };
+        amounts[nft_index as usize]
+    }
+
+    /// Wallet that NFTs collected by one side are delivered to: that side's delivery address
+    /// if it set one, otherwise the party itself.
+    pub fn delivery_owner(&self, is_initializer: bool) -> Pubkey {
+        if is_initializer {
+            self.initializer_delivery_address.unwrap_or(self.initializer)
+        } else {
+            self.taker_delivery_address.unwrap_or(self.taker)
+        }
+    }
+
+    /// Whether the NFT at `nft_index` on one side is compressed and so has no vault.
+    pub fn is_compressed(&self, is_initializer: bool, nft_index: u8) -> bool {
+        let compressed = if is_initializer {
+            &self.initializer_nft_compressed
+        } else {
+            &self.taker_nft_compressed
+        };
+        compressed[nft_index as usize]
+    }
+
+    /// Number of NFT vaults currently open: one per deposited, uncollected, uncompressed NFT,
+    /// plus the token leg's vault while it holds the payment.
+    pub fn open_vault_count(&self) -> u64 {
+        let side = |is_initializer: bool, deposited: &[bool], collected: &[bool]| {
+            (0..deposited.len())
+                .filter(|&i| deposited[i] && !collected[i] && !self.is_compressed(is_initializer, i as u8))
+                .count() as u64
+        };
+        side(true, &self.initializer_nft_deposited, &self.initializer_nft_collected)
+            + side(false, &self.taker_nft_deposited, &self.taker_nft_collected)
+            + u64::from(self.token_deposited)
+    }
+
+    /// Lamports handed back once the escrow closes: its own rent plus the rent of every open
+    /// vault, sized for the escrow's token program.
+    pub fn reclaimable_rent(&self, rent: &Rent) -> u64 {
+        let escrow_rent =
+            rent.minimum_balance(8 + EscrowAccount::space(self.initializer_nft_count, self.taker_nft_count));
+        let vault_len = if self.token_program_id == anchor_spl::token_2022::ID {
+            TOKEN_2022_ATA_LEN
+        } else {
+            anchor_spl::token::TokenAccount::LEN
+        };
+        let vault_rent = rent.minimum_balance(vault_len);
+        escrow_rent.saturating_add(vault_rent.saturating_mul(self.open_vault_count()))
+    }
+
+    /// Whether the escrow still holds a compressed NFT, which only `withdraw_compressed` and
+    /// `complete_compressed` can move.
+    pub fn holds_compressed(&self) -> bool {
+        let held = |compressed: &[bool], collected: &[bool]| {
+            compressed.iter().zip(collected.iter()).any(|(&c, &done)| c && !done)
+        };
+        held(&self.initializer_nft_compressed, &self.initializer_nft_collected)
+            || held(&self.taker_nft_compressed, &self.taker_nft_collected)
+    }
+
+    /// Whether any NFT still waiting to be collected had its metadata fingerprinted
+    pub fn tracks_metadata(&self) -> bool {
+        self.initializer_metadata_hashes
+            .iter()
+            .zip(self.initializer_nft_collected.iter())
+            .chain(self.taker_metadata_hashes.iter().zip(self.taker_nft_collected.iter()))
+            .any(|(hash, &collected)| !collected && *hash != [0; 32])
+    }
+
+    /// Checks that `signer` may deposit for one side: either the party itself or the
+    /// delegate it registered at initialization.
+    pub fn check_depositor(&self, is_initializer: bool, signer: &Pubkey) -> Result<()> {
+        let (party, delegate) = if is_initializer {
+            (self.initializer, self.initializer_delegate)
+        } else {
+            (self.taker, self.taker_delegate)
+        };
+        if *signer == party {
+            return Ok(());
+        }
+
+        match delegate {
+            Some(delegate) => {
+                require!(*signer == delegate, EscrowError::UnauthorizedDelegate);
+                Ok(())
+            }
+            None => err!(EscrowError::InvalidDepositor),
+        }
+    }
+
+    /// Whether the taker slot at `nft_index` is still waiting for a whitelisted mint.
+    pub fn is_whitelist_slot(&self, is_initializer: bool, nft_index: u8) -> bool {
+        !is_initializer
+            && !self.allowed_taker_collections.is_empty()
+            && self.taker_nft_mints[nft_index as usize] == Pubkey::default()
+    }
+
+    /// Whether deposits on one side must come with a Metaplex metadata account.
+    pub fn requires_metadata(&self, is_initializer: bool) -> bool {
+        if is_initializer {
+            self.initializer_expected_collection.is_some() || self.initializer_expected_symbol.is_some()
+        } else {
+            self.taker_expected_collection.is_some()
+                || self.taker_expected_symbol.is_some()
+                || !self.allowed_taker_collections.is_empty()
+        }
+    }
+
+    /// Drops every taker slot from `nft_count` onwards
+    pub fn truncate_taker_slots(&mut self, nft_count: u8) {
+        let len = nft_count as usize;
+        self.taker_nft_count = nft_count;
+        self.taker_nft_mints.truncate(len);
+        self.taker_nft_deposited.truncate(len);
+        self.taker_nft_collected.truncate(len);
+        self.taker_vault_funders.truncate(len);
+        self.taker_nft_deposited_at.truncate(len);
+        self.taker_metadata_hashes.truncate(len);
+        self.taker_nft_compressed.truncate(len);
+        self.taker_nft_amounts.truncate(len);
+    }
+
+    /// Replaces the NFT list for one side and resets its per-NFT bookkeeping.
+    pub fn replace_side_mints(&mut self, is_initializer: bool, mints: Vec<Pubkey>) {
+        let nft_count = mints.len();
+        if is_initializer {
+            self.initializer_nft_count = nft_count as u8;
+            self.initializer_nft_mints = mints;
+            self.initializer_nft_deposited = vec![false; nft_count];
+            self.initializer_nft_collected = vec![false; nft_count];
+            self.initializer_vault_funders = vec![Pubkey::default(); nft_count];
+            self.initializer_nft_deposited_at = vec![0; nft_count];
+            self.initializer_metadata_hashes = vec![[0; 32]; nft_count];
+            self.initializer_nft_compressed = vec![false; nft_count];
+            self.initializer_nft_amounts = vec![1; nft_count];
+        } else {
+            self.taker_nft_count = nft_count as u8;
+            self.taker_nft_mints = mints;
+            self.taker_nft_deposited = vec![false; nft_count];
+            self.taker_nft_collected = vec![false; nft_count];
+            self.taker_vault_funders = vec![Pubkey::default(); nft_count];
+            self.taker_nft_deposited_at = vec![0; nft_count];
+            self.taker_metadata_hashes = vec![[0; 32]; nft_count];
+            self.taker_nft_compressed = vec![false; nft_count];
+            self.taker_nft_amounts = vec![1; nft_count];
+        }
+    }
+
+    pub fn aggregate_flags(&self) -> AggregateFlags {
+        AggregateFlags {
+            status: self.status,
+            initializer_deposited: self.initializer_deposited,
+            taker_deposited: self.taker_deposited,
+            initializer_collected: self.initializer_collected,
+            taker_collected: self.taker_collected,
+        }
+    }
+
+    /// Recomputes the per-side aggregate collected flags. `initializer_collected` means every
+    /// initializer NFT has reached the taker, and `taker_collected` the reverse.
+    pub fn refresh_collection_status(&mut self) {
+        self.initializer_collected = self.initializer_nft_collected.iter().all(|&x| x);
+        self.taker_collected = self.taker_nft_collected.iter().all(|&x| x);
+    }
+
+    /// Refreshes the aggregate collected flags and reports whether every NFT on both sides has
+    /// now been collected. Each per-NFT flag is set at most once, under the completion lock,
+    /// so exactly one collection ever sees this turn true. That collection marks the escrow
+    /// `Completed`, and `finalize` then releases the payments and closes it.
+    pub fn finish_collection(&mut self) -> bool {
+        self.refresh_collection_status();
+        self.initializer_collected && self.taker_collected
+    }
+
+    /// Whether the taker has deposited every NFT plus any SOL and token payment.
+    pub fn taker_side_complete(&self) -> bool {
+        self.taker_nft_deposited.iter().all(|&x| x)
+            && (self.sol_amount == 0 || self.sol_deposited)
+            && (self.token_amount == 0 || self.token_deposited)
+    }
+
+    /// Rejects a token program other than the one this escrow was created with.
+    pub fn check_token_program(&self, token_program: &Pubkey) -> Result<()> {
+        require!(*token_program == self.token_program_id, EscrowError::TokenProgramMismatch);
+        Ok(())
+    }
+
+    /// Whether any NFT on either side has been collected yet.
+    pub fn collection_started(&self) -> bool {
+        self.initializer_collected
+            || self.taker_collected
+            || self.initializer_nft_collected.iter().any(|&c| c)
+            || self.taker_nft_collected.iter().any(|&c| c)
+    }
+
+    /// Whether NFTs can still be deposited at `now`. Without a deposit deadline they can
+    /// be deposited until the escrow is cancelled or refunded.
+    pub fn deposit_window_open(&self, now: i64) -> bool {
+        self.deposit_deadline == 0 || now <= self.deposit_deadline
+    }
+
+    /// Whether the taker can still pay the quoted SOL amount at `now`.
+    pub fn sol_quote_valid(&self, now: i64) -> bool {
+        self.sol_deposit_deadline == 0 || now <= self.sol_deposit_deadline
+    }
+
+    /// Deposits and withdrawals are only valid before both sides are fully funded.
+    pub fn is_accepting_deposits(&self) -> bool {
+        self.status == EscrowStatus::Created || self.status == EscrowStatus::PartiallyFunded
+    }
+
+    /// Recomputes the funding status from the deposit flags.
+    pub fn refresh_funding_status(&mut self) {
+        let any_deposited = self.initializer_nft_deposited.iter().any(|&x| x)
+            || self.taker_nft_deposited.iter().any(|&x| x)
+            || self.sol_deposited
+            || self.token_deposited;
+
+        self.status = if self.initializer_deposited && self.taker_deposited {
+            EscrowStatus::Funded
+        } else if any_deposited {
+            EscrowStatus::PartiallyFunded
+        } else {
+            EscrowStatus::Created
+        };
+    }
+
+    /// Reports both sides' running deposit totals; called at the end of every path that
+    /// deposits or withdraws an NFT.
+    pub fn emit_deposit_progress(&self, escrow: Pubkey) {
+        emit!(DepositProgress {
+            escrow,
+            initializer_deposited: self.deposited_count(true),
+            initializer_total: self.initializer_nft_count,
+            taker_deposited: self.deposited_count(false),
+            taker_total: self.taker_nft_count,
+        });
+    }
+
+    /// The maker's share of the protocol fee, taken out of the SOL the initializer receives.
+    pub fn maker_fee_amount(&self) -> u64 {
+        // Bounded by MAX_FEE_BASIS_POINTS, so this always fits back into a u64
+        (self.sol_amount as u128 * self.maker_fee_bps as u128 / 10_000) as u64
+    }
+
+    /// The taker's share of the protocol fee. The taker only receives NFTs, so there is
+    /// nothing to deduct it from; it is paid from the taker's wallet along with the SOL.
+    pub fn taker_fee_amount(&self) -> u64 {
+        (self.sol_amount as u128 * self.taker_fee_bps as u128 / 10_000) as u64
+    }
+
+    /// Whether creator royalties have to be paid when initializer NFTs are collected. They
+    /// are only charged against a SOL payment; NFT-for-NFT trades have nothing to take them from.
+    pub fn royalties_due(&self) -> bool {
+        self.enforce_royalties && self.sol_amount > 0
+    }
+
+    /// Lamports the taker deposits: the SOL payment plus their share of the fee.
+    pub fn taker_sol_due(&self) -> u64 {
+        self.sol_amount + self.taker_fee_amount()
+    }
+}
+
+/// The original escrow layout, before versioning: fixed three-slot arrays per side.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct LegacyEscrowAccount {
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub initializer_nft_count: u8,
+    pub taker_nft_count: u8,
+    pub initializer_nft_mints: [Pubkey; 3],
+    pub taker_nft_mints: [Pubkey; 3],
+    pub initializer_nft_deposited: [bool; 3],
+    pub taker_nft_deposited: [bool; 3],
+    pub initializer_nft_collected: [bool; 3],
+    pub taker_nft_collected: [bool; 3],
+    pub initializer_deposited: bool,
+    pub taker_deposited: bool,
+    pub initializer_collected: bool,
+    pub taker_collected: bool,
+    pub is_initialized: bool,
+    pub bump: u8,
+    pub created_at: i64,
+    pub timeout_in_seconds: i64,
+}
+
+impl LegacyEscrowAccount {
+    /// Converts to the current layout, defaulting every field the old layout lacked.
+    pub fn upgrade(&self) -> EscrowAccount {
+        let initializer_nft_count = (self.initializer_nft_count as usize).min(3);
+        let taker_nft_count = (self.taker_nft_count as usize).min(3);
+
+        // Legacy vaults were always paid for by the depositing party
+        let funders = |party: Pubkey, deposited: &[bool]| -> Vec<Pubkey> {
+            deposited
+                .iter()
+                .map(|&d| if d { party } else { Pubkey::default() })
+                .collect()
+        };
+
+        let mut escrow = EscrowAccount {
+            version: ESCROW_ACCOUNT_VERSION,
+            initializer: self.initializer,
+            taker: self.taker,
+            initializer_nft_count: initializer_nft_count as u8,
+            taker_nft_count: taker_nft_count as u8,
+            initializer_nft_mints: self.initializer_nft_mints[..initializer_nft_count].to_vec(),
+            taker_nft_mints: self.taker_nft_mints[..taker_nft_count].to_vec(),
+            initializer_nft_deposited: self.initializer_nft_deposited[..initializer_nft_count].to_vec(),
+            taker_nft_deposited: self.taker_nft_deposited[..taker_nft_count].to_vec(),
+            initializer_nft_collected: self.initializer_nft_collected[..initializer_nft_count].to_vec(),
+            taker_nft_collected: self.taker_nft_collected[..taker_nft_count].to_vec(),
+            initializer_deposited: self.initializer_deposited,
+            taker_deposited: self.taker_deposited,
+            initializer_collected: self.initializer_collected,
+            taker_collected: self.taker_collected,
+            is_initialized: self.is_initialized,
+            bump: self.bump,
+            created_at: self.created_at,
+            timeout_in_seconds: self.timeout_in_seconds,
+            sol_amount: 0,
+            sol_deposited: false,
+            maker_fee_bps: 0,
+            fee_recipient: self.initializer,
+            initializer_expected_collection: None,
+            taker_expected_collection: None,
+            status: EscrowStatus::Created,
+            timeout_extension_seconds: 0,
+            initializer_vault_funders: funders(
+                self.initializer,
+                &self.initializer_nft_deposited[..initializer_nft_count],
+            ),
+            taker_vault_funders: funders(self.taker, &self.taker_nft_deposited[..taker_nft_count]),
+            nonce: 0,
+            is_open_offer: false,
+            pending_acceptance: false,
+            initializer_ready: false,
+            taker_ready: false,
+            uses_legacy_seeds: true,
+            initializer_delegate: None,
+            taker_delegate: None,
+            allowed_taker_collections: Vec::new(),
+            token_mint: Pubkey::default(),
+            token_amount: 0,
+            token_deposited: false,
+            token_vault_funder: Pubkey::default(),
+            in_progress: false,
+            initializer_expected_symbol: None,
+            taker_expected_symbol: None,
+            last_collector: Pubkey::default(),
+            deposit_deadline: 0,
+            rent_payer: self.initializer,
+            taker_fee_bps: 0,
+            // Escrows from before token-2022 support only ever used the SPL Token program
+            token_program_id: anchor_spl::token::ID,
+            // Legacy accounts never recorded when their NFTs arrived
+            initializer_nft_deposited_at: vec![0; initializer_nft_count],
+            taker_nft_deposited_at: vec![0; taker_nft_count],
+            arbiter: None,
+            sol_deposit_deadline: 0,
+            enforce_royalties: false,
+            royalties_paid: 0,
+            // Legacy escrows predate the active escrow counter
+            counted_as_active: false,
+            sliding_timeout: false,
+            last_activity_at: self.created_at,
+            seed_initializer: self.initializer,
+            block_on_metadata_change: false,
+            initializer_metadata_hashes: vec![[0; 32]; initializer_nft_count],
+            taker_metadata_hashes: vec![[0; 32]; taker_nft_count],
+            keeper_grace_seconds: 0,
+            memo: [0; 32],
+            initializer_nft_compressed: vec![false; initializer_nft_count],
+            taker_nft_compressed: vec![false; taker_nft_count],
+            approver: None,
+            approved: true,
+            initializer_nft_amounts: vec![1; initializer_nft_count],
+            taker_nft_amounts: vec![1; taker_nft_count],
+            initializer_delivery_address: None,
+            taker_delivery_address: None,
+            // Whatever lists a legacy escrow sits in can still be cleaned up by passing them
+            listed_initializer: false,
+            listed_taker: false,
+        };
+
+        escrow.refresh_funding_status();
+        if escrow.initializer_nft_collected.iter().any(|&x| x)
+            || escrow.taker_nft_collected.iter().any(|&x| x)
+        {
+            escrow.status = EscrowStatus::Completing;
+        }
+
+        escrow
+    }
+}
+
+/// Checks that `metadata` describes `mint` and that it belongs to `expected_collection`
+/// with a verified collection entry.
+pub fn verify_collection(
+    metadata: &MetadataAccount,
+    mint: &Pubkey,
+    expected_collection: &Pubkey,
+) -> Result<()> {
+    require!(metadata.mint == *mint, EscrowError::InvalidMetadataAccount);
+
+    let collection = metadata
+        .collection
+        .as_ref()
+        .ok_or(EscrowError::CollectionMismatch)?;
+    require!(collection.key == *expected_collection, EscrowError::CollectionMismatch);
+    require!(collection.verified, EscrowError::CollectionNotVerified);
+
+    Ok(())
+}
+
+/// Checks that `metadata` describes `mint` and carries `expected_symbol`. Metaplex pads
+/// symbols with null bytes, so trailing zeros are ignored on both sides.
+pub fn verify_symbol(
+    metadata: &MetadataAccount,
+    mint: &Pubkey,
+    expected_symbol: &[u8; MAX_SYMBOL_LENGTH],
+) -> Result<()> {
+    require!(metadata.mint == *mint, EscrowError::InvalidMetadataAccount);
+
+    let trim = |bytes: &[u8]| -> usize {
+        bytes.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1)
+    };
+    let actual = metadata.symbol.as_bytes();
+    require!(
+        actual[..trim(actual)] == expected_symbol[..trim(expected_symbol)],
+        EscrowError::MetadataMismatch
+    );
+
+    Ok(())
+}
+
+/// Pays the creators of an initializer NFT their royalty out of the escrowed SOL and
+/// returns the total paid. The NFT's share of the SOL is an even split across all
+/// initializer NFTs, and the royalty is capped at what is left of the payment.
+///
+/// `creators` must hold every creator listed in `metadata` with a non-zero share, in order.
+pub fn pay_royalties<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    mint: &Pubkey,
+    metadata: Option<&MetadataAccount>,
+    creators: &[AccountInfo<'info>],
+) -> Result<u64> {
+    let metadata = metadata.ok_or(EscrowError::RoyaltyAccountMissing)?;
+    require!(metadata.mint == *mint, EscrowError::InvalidMetadataAccount);
+
+    let sale_price = escrow.sol_amount / escrow.initializer_nft_count as u64;
+    let remaining = escrow
+        .sol_amount
+        .checked_sub(escrow.maker_fee_amount())
+        .and_then(|left| left.checked_sub(escrow.royalties_paid))
+        .ok_or(EscrowError::RoyaltyOverflow)?;
+    let royalty = u64::try_from(sale_price as u128 * metadata.seller_fee_basis_points as u128 / 10_000)
+        .map_err(|_| EscrowError::RoyaltyOverflow)?;
+    let royalty = royalty.min(remaining);
+
+    let payees: Vec<_> = metadata
+        .creators
+        .iter()
+        .flatten()
+        .filter(|creator| creator.share > 0)
+        .collect();
+    require!(creators.len() >= payees.len(), EscrowError::RoyaltyAccountMissing);
+
+    let escrow_info = escrow.to_account_info();
+    let mut paid: u64 = 0;
+    for (creator, creator_info) in payees.into_iter().zip(creators.iter()) {
+        require!(creator_info.key() == creator.address, EscrowError::RoyaltyAccountMissing);
+
+        let amount = royalty
+            .checked_mul(creator.share as u64)
+            .ok_or(EscrowError::RoyaltyOverflow)?
+            / 100;
+        let escrow_lamports = escrow_info
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(EscrowError::RoyaltyOverflow)?;
+        let creator_lamports = creator_info
+            .lamports()
+            .checked_add(amount)
+            .ok_or(EscrowError::RoyaltyOverflow)?;
+        **escrow_info.lamports.borrow_mut() = escrow_lamports;
+        **creator_info.lamports.borrow_mut() = creator_lamports;
+        paid = paid.checked_add(amount).ok_or(EscrowError::RoyaltyOverflow)?;
+    }
+
+    msg!("Paid {} lamports in royalties for NFT {}", paid, mint);
+
+    Ok(paid)
+}
+
+/// Checks that `metadata` describes `mint` and that its verified collection is one of
+/// `allowed_collections`.
+pub fn verify_collection_whitelist(
+    metadata: &MetadataAccount,
+    mint: &Pubkey,
+    allowed_collections: &[Pubkey],
+) -> Result<()> {
+    require!(metadata.mint == *mint, EscrowError::InvalidMetadataAccount);
+
+    let collection = metadata
+        .collection
+        .as_ref()
+        .ok_or(EscrowError::MintNotInWhitelist)?;
+    require!(
+        allowed_collections.contains(&collection.key),
+        EscrowError::MintNotInWhitelist
+    );
+    require!(collection.verified, EscrowError::CollectionNotVerified);
+
+    Ok(())
+}
+
+/// Validates the swap terms shared by `initialize` and `initialize_open` and writes them
+/// into a freshly created escrow account.
+#[allow(clippy::too_many_arguments)]
+pub fn setup_escrow(
+    escrow: &mut EscrowAccount,
+    initializer: Pubkey,
+    taker: Pubkey,
+    remaining_accounts: &[AccountInfo],
+    initializer_nft_count: u8,
+    taker_nft_count: u8,
+    bump: u8,
+    timeout_in_seconds: i64,
+    sol_amount: u64,
+    maker_fee_bps: u16,
+    taker_fee_bps: u16,
+    fee_recipient: Pubkey,
+    initializer_collection: Option<Pubkey>,
+    taker_collection: Option<Pubkey>,
+    initializer_delegate: Option<Pubkey>,
+    taker_delegate: Option<Pubkey>,
+    allowed_taker_collections: Vec<Pubkey>,
+    token_mint: Pubkey,
+    token_amount: u64,
+    initializer_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+    taker_symbol: Option<[u8; MAX_SYMBOL_LENGTH]>,
+    deposit_deadline: i64,
+    arbiter: Option<Pubkey>,
+    sol_deposit_deadline: i64,
+    enforce_royalties: bool,
+    sliding_timeout: bool,
+    block_on_metadata_change: bool,
+    keeper_grace_seconds: i64,
+    memo: [u8; 32],
+    approver: Option<Pubkey>,
+    nft_amounts: Vec<u64>,
+    initializer_delivery_address: Option<Pubkey>,
+    taker_delivery_address: Option<Pubkey>,
+) -> Result<()> {
+    // Validate NFT counts (up to MAX_NFTS_PER_SIDE NFTs per participant).
+    // The taker may provide no NFTs when paying purely in SOL or tokens; the initializer has
+    // no cash leg, so it always provides at least one.
+    require!(
+        initializer_nft_count > 0 || taker_nft_count > 0,
+        EscrowError::EmptyBothSides
+    );
+    require!(
+        initializer_nft_count > 0 && initializer_nft_count <= MAX_NFTS_PER_SIDE,
+        EscrowError::InvalidNftCount
+    );
+    require!(
+        (taker_nft_count > 0 || sol_amount > 0 || token_amount > 0)
+            && taker_nft_count <= MAX_NFTS_PER_SIDE,
+        EscrowError::InvalidNftCount
+    );
+
+    // With a collection whitelist the taker picks any NFTs from those collections at deposit time
+    let taker_mints_from_whitelist = !allowed_taker_collections.is_empty();
+    require!(
+        allowed_taker_collections.len() <= MAX_ALLOWED_TAKER_COLLECTIONS,
+        EscrowError::TooManyAllowedCollections
+    );
+    require!(
+        !taker_mints_from_whitelist || taker_nft_count > 0,
+        EscrowError::InvalidNftCount
+    );
+
+    // A timeout of 0 means the caller wants the default window
+    let timeout_in_seconds = if timeout_in_seconds == 0 {
+        DEFAULT_TIMEOUT_SECONDS
+    } else {
+        timeout_in_seconds
+    };
+    require!(
+        timeout_in_seconds >= MIN_TIMEOUT_SECONDS && timeout_in_seconds <= MAX_TIMEOUT_SECONDS,
+        EscrowError::InvalidTimeout
+    );
+    // Keepers may be held back past the timeout, but by no more than a full timeout window
+    require!(
+        (0..=MAX_TIMEOUT_SECONDS).contains(&keeper_grace_seconds),
+        EscrowError::InvalidTimeout
+    );
+
+    // NFT deposits may be cut off before the cancellation timeout, but never after it.
+    // A deadline of 0 leaves deposits open until the timeout.
+    let created_at = Clock::get()?.unix_timestamp;
+    // A zero or negative clock would put every deadline and the timeout in the past
+    require!(created_at > 0, EscrowError::InvalidClock);
+    require!(
+        deposit_deadline == 0
+            || (deposit_deadline > created_at && deposit_deadline <= created_at + timeout_in_seconds),
+        EscrowError::InvalidTimeout
+    );
+    // The SOL quote can go stale sooner than the NFT deadline; 0 means it never does
+    require!(
+        sol_deposit_deadline == 0
+            || (sol_amount > 0
+                && sol_deposit_deadline > created_at
+                && sol_deposit_deadline <= created_at + timeout_in_seconds),
+        EscrowError::InvalidTimeout
+    );
+
+    // Each slot may ask for more than one token of a semi-fungible mint. An empty list keeps
+    // every slot at a single NFT; otherwise there is one amount per slot, initializer's first.
+    require!(
+        nft_amounts.is_empty()
+            || (nft_amounts.len() == initializer_nft_count as usize + taker_nft_count as usize
+                && nft_amounts.iter().all(|&amount| amount > 0)),
+        EscrowError::InvalidNftAmounts
+    );
+
+    // A token leg needs both a mint and a non-zero amount
+    require!(
+        token_amount == 0 || token_mint != Pubkey::default(),
+        EscrowError::InvalidTokenAmount
+    );
+
+    // The protocol fee is priced off the SOL payment and may be split between both parties
+    require!(
+        maker_fee_bps as u32 + taker_fee_bps as u32 <= MAX_FEE_BASIS_POINTS as u32,
+        EscrowError::FeeTooHigh
+    );
+
+    // Initialize the escrow account
+    escrow.version = ESCROW_ACCOUNT_VERSION;
+    escrow.initializer = initializer;
+    escrow.seed_initializer = initializer;
+    escrow.taker = taker;
+    escrow.initializer_nft_count = initializer_nft_count;
+    escrow.taker_nft_count = taker_nft_count;
+    escrow.is_initialized = true;
+    escrow.status = EscrowStatus::Created;
+    escrow.initializer_deposited = false;
+    escrow.taker_deposited = false;
+    // Canonical bump found by Anchor when deriving the escrow PDA
+    escrow.bump = bump;
+    escrow.created_at = created_at;
+    escrow.timeout_in_seconds = timeout_in_seconds;
+    escrow.deposit_deadline = deposit_deadline;
+    escrow.arbiter = arbiter;
+    escrow.sol_deposit_deadline = sol_deposit_deadline;
+    escrow.enforce_royalties = enforce_royalties;
+    escrow.royalties_paid = 0;
+    escrow.counted_as_active = false;
+    escrow.sliding_timeout = sliding_timeout;
+    escrow.block_on_metadata_change = block_on_metadata_change;
+    escrow.keeper_grace_seconds = keeper_grace_seconds;
+    escrow.memo = memo;
+    // With an approver, nothing can be deposited until they sign off on the escrow
+    escrow.approver = approver;
+    escrow.approved = approver.is_none();
+    escrow.initializer_delivery_address = initializer_delivery_address;
+    escrow.taker_delivery_address = taker_delivery_address;
+    escrow.listed_initializer = false;
+    escrow.listed_taker = false;
+    escrow.last_activity_at = created_at;
+    escrow.sol_amount = sol_amount;
+    escrow.sol_deposited = false;
+    escrow.maker_fee_bps = maker_fee_bps;
+    escrow.taker_fee_bps = taker_fee_bps;
+    // Without a fee there is nothing to route, so point the recipient at the initializer
+    escrow.fee_recipient = if maker_fee_bps == 0 && taker_fee_bps == 0 {
+        escrow.initializer
+    } else {
+        fee_recipient
+    };
+    escrow.initializer_expected_collection = initializer_collection;
+    escrow.taker_expected_collection = taker_collection;
+    escrow.initializer_delegate = initializer_delegate;
+    escrow.taker_delegate = taker_delegate;
+    escrow.allowed_taker_collections = allowed_taker_collections;
+    escrow.token_mint = if token_amount > 0 { token_mint } else { Pubkey::default() };
+    escrow.token_amount = token_amount;
+    escrow.token_deposited = false;
+    escrow.initializer_expected_symbol = initializer_symbol;
+    escrow.taker_expected_symbol = taker_symbol;
+
+    // Every mint must be supplied via remaining_accounts: initializer's first, then taker's.
+    // Whitelisted taker slots have no mint until the taker deposits.
+    let listed_taker_nft_count = if taker_mints_from_whitelist { 0 } else { taker_nft_count };
+    require!(
+        remaining_accounts.len() == (initializer_nft_count as usize + listed_taker_nft_count as usize),
+        EscrowError::MissingMintAccounts
+    );
+
+    // The same mint on both sides would swap an NFT for itself
+    let (initializer_mints, taker_mints) = remaining_accounts.split_at(initializer_nft_count as usize);
+    for a in initializer_mints {
+        require!(
+            taker_mints.iter().all(|b| a.key() != b.key()),
+            EscrowError::CrossSideDuplicateMint
+        );
+    }
+
+    // Each mint may only appear once across both sides, otherwise one NFT
+    // could satisfy two indices
+    for (i, a) in remaining_accounts.iter().enumerate() {
+        for b in remaining_accounts.iter().skip(i + 1) {
+            require!(a.key() != b.key(), EscrowError::DuplicateMint);
+        }
+    }
+
+    // Store the mint addresses for initializer's NFTs
+    escrow.initializer_nft_mints = Vec::with_capacity(initializer_nft_count as usize);
+    for i in 0..initializer_nft_count as usize {
+        let mint_info = remaining_accounts
+            .get(i)
+            .ok_or(EscrowError::MissingMintAccounts)?;
+        escrow.initializer_nft_mints.push(mint_info.key());
+    }
+
+    // Store the mint addresses for taker's NFTs
+    if taker_mints_from_whitelist {
+        escrow.taker_nft_mints = vec![Pubkey::default(); taker_nft_count as usize];
+    } else {
+        escrow.taker_nft_mints = Vec::with_capacity(taker_nft_count as usize);
+        for i in 0..taker_nft_count as usize {
+            let idx = (initializer_nft_count as usize)
+                .checked_add(i)
+                .ok_or(EscrowError::MissingMintAccounts)?;
+            let mint_info = remaining_accounts
+                .get(idx)
+                .ok_or(EscrowError::MissingMintAccounts)?;
+            escrow.taker_nft_mints.push(mint_info.key());
+        }
+    }
+
+    // Per-NFT deposit/collection bitmaps sized to each side's count
+    escrow.initializer_nft_deposited = vec![false; initializer_nft_count as usize];
+    escrow.taker_nft_deposited = vec![false; taker_nft_count as usize];
+    escrow.initializer_nft_collected = vec![false; initializer_nft_count as usize];
+    escrow.taker_nft_collected = vec![false; taker_nft_count as usize];
+
+    // Whoever first deposits into a vault pays its rent and is recorded here
+    escrow.initializer_vault_funders = vec![Pubkey::default(); initializer_nft_count as usize];
+    escrow.taker_vault_funders = vec![Pubkey::default(); taker_nft_count as usize];
+    escrow.initializer_nft_deposited_at = vec![0; initializer_nft_count as usize];
+    escrow.taker_nft_deposited_at = vec![0; taker_nft_count as usize];
+    escrow.initializer_metadata_hashes = vec![[0; 32]; initializer_nft_count as usize];
+    escrow.taker_metadata_hashes = vec![[0; 32]; taker_nft_count as usize];
+    escrow.initializer_nft_compressed = vec![false; initializer_nft_count as usize];
+    escrow.taker_nft_compressed = vec![false; taker_nft_count as usize];
+    if nft_amounts.is_empty() {
+        escrow.initializer_nft_amounts = vec![1; initializer_nft_count as usize];
+        escrow.taker_nft_amounts = vec![1; taker_nft_count as usize];
+    } else {
+        let (initializer_amounts, taker_amounts) = nft_amounts.split_at(initializer_nft_count as usize);
+        escrow.initializer_nft_amounts = initializer_amounts.to_vec();
+        escrow.taker_nft_amounts = taker_amounts.to_vec();
+    }
+
+    msg!("Initializer will provide {} NFTs", initializer_nft_count);
+    msg!("Taker will provide {} NFTs", taker_nft_count);
+    if sol_amount > 0 {
+        msg!("Taker will pay {} lamports plus a {} lamport fee", sol_amount, escrow.taker_fee_amount());
+    }
+    if token_amount > 0 {
+        msg!("Taker will pay {} of token {}", token_amount, token_mint);
+    }
+
+    Ok(())
+}
+
+/// Moves the NFT at `nft_index` into `vault_account` and records the deposit. Shared by
+/// `deposit` and `deposit_to_vault`; `vault_funder` gets the vault's rent back when it closes.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_nft<'info>(
+    escrow: &mut Account<'info, EscrowAccount>,
+    is_initializer: bool,
+    nft_index: u8,
+    depositor: &Signer<'info>,
+    vault_funder: Pubkey,
+    mint: &InterfaceAccount<'info, Mint>,
+    metadata_account: Option<&MetadataAccount>,
+    token_account: &InterfaceAccount<'info, TokenAccount>,
+    vault_account: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    
+    check_deposit_allowed(
+        escrow,
+        is_initializer,
+        &depositor.key(),
+        token_program.key,
+        Clock::get()?.unix_timestamp,
+    )?;
+    
+    // Verify the token account holds the NFT expected at this index
+    let expected_mint = validate_nft_deposit(
+        escrow,
+        is_initializer,
+        nft_index,
+        mint,
+        token_account,
+        metadata_account,
+        &depositor.key(),
+    )?;
+    
+    // Verify vault account
+    require!(vault_account.mint == expected_mint, EscrowError::InvalidNftMint);
+    require!(vault_account.owner == escrow.key(), EscrowError::InvalidVaultAuthority);
+    
+    // Transfer the NFT to the vault
+    let cpi_accounts = token_interface::TransferChecked {
+        from: token_account.to_account_info(),
+        mint: mint.to_account_info(),
+        to: vault_account.to_account_info(),
+        authority: depositor.to_account_info(),
+    };
+    
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+    
+    token_interface::transfer_checked(cpi_ctx, escrow.nft_amount(is_initializer, nft_index), mint.decimals)?;
+    
+    msg!("Transferred NFT {} to escrow vault", expected_mint);
+
+    // Remember who paid for the vault so its rent can be returned to them
+    escrow.record_vault_funder(is_initializer, nft_index, vault_funder);
+
+    let deposited_at = Clock::get()?.unix_timestamp;
+    escrow.last_activity_at = deposited_at;
+    escrow.record_deposit_time(is_initializer, nft_index, deposited_at);
+    // Fingerprint the metadata so `complete` can tell if it is edited while in escrow
+    if let Some(metadata) = metadata_account {
+        require!(metadata.mint == expected_mint, EscrowError::InvalidMetadataAccount);
+        escrow.record_metadata_hash(is_initializer, nft_index, hash_metadata(metadata));
+    }
+    emit!(NftDeposited {
+        escrow: escrow.key(),
+        depositor: depositor.key(),
+        mint: expected_mint,
+        is_initializer,
+        nft_index,
+        deposited_at,
+    });
+    
+    // Mark this NFT as deposited
+    if is_initializer {
+        escrow.initializer_nft_deposited[nft_index as usize] = true;
+        
+        // Check if all initializer NFTs have been deposited
+        let mut all_deposited = true;
+        for i in 0..escrow.initializer_nft_count as usize {
+            if !escrow.initializer_nft_deposited[i] {
+                all_deposited = false;
+                break;
+            }
+        }
+        
+        if all_deposited {
+            escrow.initializer_deposited = true;
+            msg!("Initializer has deposited all NFTs");
+        }
+    } else {
+        // Whitelisted slots learn their mint here
+        escrow.taker_nft_mints[nft_index as usize] = expected_mint;
+        escrow.taker_nft_deposited[nft_index as usize] = true;
+        
+        // Check if all taker NFTs have been deposited
+        let mut all_deposited = true;
+        for i in 0..escrow.taker_nft_count as usize {
+            if !escrow.taker_nft_deposited[i] {
+                all_deposited = false;
+                break;
+            }
+        }
+        
+        if all_deposited {
+            msg!("Taker has deposited all NFTs");
+
+            // The taker's side is only complete once any SOL or token payment has landed too
+            if escrow.taker_side_complete() {
+                escrow.taker_deposited = true;
+            }
+        }
+    }
+    
+    // Check if both parties have deposited all their NFTs
+    escrow.refresh_funding_status();
+    if escrow.status == EscrowStatus::Funded {
+        msg!("All NFTs have been deposited. Escrow is ready for completion.");
+    }
+
+    escrow.emit_deposit_progress(escrow.key());
+    
+    Ok(())
+}
+
+/// Escrow-level checks shared by `deposit` and `validate_deposit`: the escrow is still taking
+/// deposits, the depositor may deposit for this side, and the side isn't already complete
+pub fn check_deposit_allowed(
+    escrow: &EscrowAccount,
+    is_initializer: bool,
+    depositor: &Pubkey,
+    token_program: &Pubkey,
+    now: i64,
+) -> Result<()> {
+    require!(escrow.is_initialized, EscrowError::EscrowNotInitialized);
+    // Once either side has started collecting, the escrow is settling and takes no more NFTs
+    require!(!escrow.collection_started(), EscrowError::CannotDepositDuringSettlement);
+    require!(escrow.is_accepting_deposits(), EscrowError::InvalidEscrowStatus);
+    require!(escrow.deposit_window_open(now), EscrowError::DepositWindowClosed);
+    // Deposits wait until the initializer has accepted any counter-offer
+    require!(!escrow.pending_acceptance, EscrowError::CounterPending);
+    require!(escrow.approved, EscrowError::EscrowNotApproved);
+
+    // Verify the depositor is the party itself or its registered delegate
+    escrow.check_depositor(is_initializer, depositor)?;
+    // Every transfer for this escrow must go through the token program it was created with
+    escrow.check_token_program(token_program)?;
+    if is_initializer {
+        require!(!escrow.initializer_deposited, EscrowError::AlreadyDeposited);
+    } else {
+        require!(!escrow.taker_deposited, EscrowError::AlreadyDeposited);
+    }
+
+    Ok(())
+}
+
+/// Runs the per-NFT `deposit` checks for the NFT at `nft_index` on the depositing side
+/// and returns the mint expected at that index. The token account must belong to the
+/// party itself even when a delegate is signing.
+pub fn validate_nft_deposit(
+    escrow: &EscrowAccount,
+    is_initializer: bool,
+    nft_index: u8,
+    mint: &Mint,
+    token_account: &TokenAccount,
+    metadata: Option<&MetadataAccount>,
+    depositor: &Pubkey,
+) -> Result<Pubkey> {
+    let (owner, nft_mints, nft_deposited, expected_collection, expected_symbol) = if is_initializer {
+        (
+            escrow.initializer,
+            &escrow.initializer_nft_mints,
+            &escrow.initializer_nft_deposited,
+            escrow.initializer_expected_collection,
+            escrow.initializer_expected_symbol,
+        )
+    } else {
+        (
+            escrow.taker,
+            &escrow.taker_nft_mints,
+            &escrow.taker_nft_deposited,
+            escrow.taker_expected_collection,
+            escrow.taker_expected_symbol,
+        )
+    };
+
+    require!((nft_index as usize) < nft_mints.len(), EscrowError::InvalidNftIndex);
+
+    // Check if this NFT has already been deposited
+    require!(!nft_deposited[nft_index as usize], EscrowError::NftAlreadyDeposited);
+
+    // A whitelisted taker slot accepts any unlisted mint from an allowed collection
+    let expected_mint = if escrow.is_whitelist_slot(is_initializer, nft_index) {
+        let candidate_mint = token_account.mint;
+        require!(
+            !escrow.initializer_nft_mints.contains(&candidate_mint)
+                && !escrow.taker_nft_mints.contains(&candidate_mint),
+            EscrowError::DuplicateMint
+        );
+        let metadata = metadata.ok_or(EscrowError::MissingMetadataAccount)?;
+        verify_collection_whitelist(metadata, &candidate_mint, &escrow.allowed_taker_collections)?;
+        candidate_mint
+    } else {
+        nft_mints[nft_index as usize]
+    };
+
+    require!(token_account.owner == owner, EscrowError::InvalidTokenAccount);
+    // Log both mints so a failed deposit shows which NFT was wrong
+    if token_account.mint != expected_mint {
+        msg!(
+            "Expected mint {} at index {}, but the deposit provided {}",
+            expected_mint,
+            nft_index,
+            token_account.mint
+        );
+        return err!(EscrowError::InvalidNftMint);
+    }
+    let expected_amount = escrow.nft_amount(is_initializer, nft_index);
+    require!(token_account.amount == expected_amount, EscrowError::InvalidTokenAmount);
+    // A frozen account would only fail later inside the token program
+    require!(!token_account.is_frozen(), EscrowError::TokenAccountFrozen);
+    // Anyone holding an SPL approval could move the NFT out from under the escrow. The only
+    // approval allowed is the one that lets a registered delegate make this very deposit.
+    if let COption::Some(spl_delegate) = token_account.delegate {
+        require!(
+            token_account.delegated_amount == 0 || spl_delegate == *depositor,
+            EscrowError::TokenAccountHasDelegate
+        );
+    }
+
+    // Verify the mint itself is a genuine NFT, or for a multi-token slot a whole-unit mint
+    // with at least that many tokens in circulation
+    let mint_ok = if expected_amount == 1 {
+        mint.supply == 1
+    } else {
+        mint.supply >= expected_amount
+    };
+    require!(mint.decimals == 0 && mint_ok, EscrowError::NotAnNft);
+
+    // Verify the NFT belongs to the expected verified collection, if one was specified
+    if let Some(expected_collection) = expected_collection {
+        let metadata = metadata.ok_or(EscrowError::MissingMetadataAccount)?;
+        verify_collection(metadata, &expected_mint, &expected_collection)?;
+    }
+
+    // Verify the on-chain symbol matches what was agreed, if one was specified
+    if let Some(expected_symbol) = expected_symbol {
+        let metadata = metadata.ok_or(EscrowError::MissingMetadataAccount)?;
+        verify_symbol(metadata, &expected_mint, &expected_symbol)?;
+    }
+
+    // Completion is gated on the metadata staying put, so it must be fingerprinted on the way in
+    if escrow.block_on_metadata_change {
+        let metadata = metadata.ok_or(EscrowError::MissingMetadataAccount)?;
+        require!(metadata.mint == expected_mint, EscrowError::InvalidMetadataAccount);
+    }
+
+    Ok(expected_mint)
+}
+
+/// Keeps the indices in `pending` that fall within `start_index..start_index + count`,
+/// rejecting an empty slice or one that runs past the side's `nft_count`.
+pub fn chunk_indices(pending: &[u8], nft_count: u8, start_index: u8, count: u8) -> Result<Vec<u8>> {
+    let end = start_index
+        .checked_add(count)
+        .ok_or(EscrowError::InvalidNftIndex)?;
+    require!(count > 0 && end <= nft_count, EscrowError::InvalidNftIndex);
+
+    Ok(pending
+        .iter()
+        .copied()
+        .filter(|&i| i >= start_index && i < end)
+        .collect())
+}
+
+/// Deposits every NFT in `pending` for one side, creating vaults as needed. `depositor` signs
+/// the transfers and pays for the vaults; it may be the party or its delegate.
+///
+/// Each pending NFT is supplied in `remaining_accounts` as (mint, depositor token account, vault),
+/// followed by its metadata account when the side has an expected collection, a whitelist or
+/// an expected symbol. There must be exactly one group per pending index, in index order, and
+/// every group is validated before the first transfer runs.
+/// Returns the mint deposited at each pending index.
+#[allow(clippy::too_many_arguments)]
+pub fn deposit_pending_nfts<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    is_initializer: bool,
+    pending: &[u8],
+    depositor: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<Vec<Pubkey>> {
+    let needs_metadata = escrow.requires_metadata(is_initializer);
+    let group_len = if needs_metadata { 4 } else { 3 };
+    require!(
+        remaining_accounts.len() == pending.len() * group_len,
+        EscrowError::MissingMintAccounts
+    );
+
+    let mut deposited_mints = Vec::with_capacity(pending.len());
+    let mut mint_decimals = Vec::with_capacity(pending.len());
+
+    // First pass: check every group against the stored mints without moving anything
+    for (k, &nft_index) in pending.iter().enumerate() {
+        let accounts = &remaining_accounts[k * group_len..(k + 1) * group_len];
+        let mint_info = &accounts[0];
+        let token_info = &accounts[1];
+
+        // Listed slots must be supplied with exactly their stored mint
+        if !escrow.is_whitelist_slot(is_initializer, nft_index) {
+            let stored_mints = if is_initializer {
+                &escrow.initializer_nft_mints
+            } else {
+                &escrow.taker_nft_mints
+            };
+            require!(
+                stored_mints.get(nft_index as usize) == Some(&mint_info.key()),
+                EscrowError::InvalidNftMint
+            );
+        }
+
+        let mint = load_mint(mint_info, token_program.key)?;
+        let token_account = load_token_account(token_info, token_program.key)?;
+        let metadata = if needs_metadata {
+            Some(load_metadata(&accounts[3])?)
+        } else {
+            None
+        };
+
+        let expected_mint = validate_nft_deposit(
+            escrow,
+            is_initializer,
+            nft_index,
+            &mint,
+            &token_account,
+            metadata.as_ref(),
+            depositor.key,
+        )?;
+        require!(mint_info.key() == expected_mint, EscrowError::InvalidNftMint);
+        // Whitelisted slots are only checked against stored mints, so also reject
+        // the same mint twice within this batch
+        require!(!deposited_mints.contains(&expected_mint), EscrowError::DuplicateMint);
+        deposited_mints.push(expected_mint);
+        mint_decimals.push(mint.decimals);
+    }
+
+    // Second pass: every group checked out, so create the vaults and move the NFTs
+    for (k, expected_mint) in deposited_mints.iter().enumerate() {
+        let accounts = &remaining_accounts[k * group_len..(k + 1) * group_len];
+        let mint_info = &accounts[0];
+        let token_info = &accounts[1];
+        let vault_info = &accounts[2];
+
+        // Create the vault ATA owned by the escrow PDA if it does not exist yet
+        let cpi_accounts = associated_token::Create {
+            payer: depositor.clone(),
+            associated_token: vault_info.clone(),
+            authority: escrow.to_account_info(),
+            mint: mint_info.clone(),
+            system_program: system_program.clone(),
+            token_program: token_program.clone(),
+        };
+        let cpi_ctx = CpiContext::new(associated_token_program.clone(), cpi_accounts);
+        associated_token::create_idempotent(cpi_ctx)?;
+
+        // Transfer the NFT to the vault
+        let cpi_accounts = token_interface::TransferChecked {
+            from: token_info.clone(),
+            mint: mint_info.clone(),
+            to: vault_info.clone(),
+            authority: depositor.clone(),
+        };
+        let cpi_ctx = CpiContext::new(token_program.clone(), cpi_accounts);
+
+        token_interface::transfer_checked(
+            cpi_ctx,
+            escrow.nft_amount(is_initializer, pending[k]),
+            mint_decimals[k],
+        )?;
+
+        msg!("Transferred NFT {} to escrow vault", expected_mint);
+    }
+
+    Ok(deposited_mints)
+}
+
+/// Fails unless `vault` is the escrow's empty vault for an NFT that is deposited but not yet
+/// collected, which means the swap can never finish
+pub fn check_unfulfillable(
+    escrow: &Account<EscrowAccount>,
+    vault: &AccountInfo,
+    token_program: &Pubkey,
+) -> Result<()> {
+    let owed = escrow
+        .unmatched_nfts(true)
+        .into_iter()
+        .map(|i| escrow.initializer_nft_mints[i as usize])
+        .chain(
+            escrow
+                .unmatched_nfts(false)
+                .into_iter()
+                .map(|i| escrow.taker_nft_mints[i as usize]),
+        )
+        .any(|mint| vault.key() == escrow_vault_address(&escrow.key(), &mint, token_program));
+    require!(owed, EscrowError::SwapUnfulfillable);
+
+    let vault = load_token_account(vault, token_program)?;
+    require!(vault.amount == 0, EscrowError::SwapUnfulfillable);
+
+    Ok(())
 }
 
-#[derive(Accounts)]
-#[instruction(is_initializer: bool, nft_index: u8)]
-pub struct Complete<'info> {
-    #[account(mut)]
-    pub caller: Signer<'info>,
-    #[account(
-        mut,
-        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
-        constraint = (caller.key() == escrow_account.initializer || 
-                   caller.key() == escrow_account.taker) @ EscrowError::InvalidCaller,
-        constraint = escrow_account.initializer_deposited && escrow_account.taker_deposited @ EscrowError::DepositsIncomplete
-    )]
-    pub escrow_account: Account<'info, EscrowAccount>,
-    /// CHECK: This is the initializer who will receive the rent refund when the escrow is closed
-    #[account(mut, address = escrow_account.initializer)]
-    pub initializer: UncheckedAccount<'info>,
-    pub mint: Account<'info, Mint>,
-    #[account(
-        mut,
-        constraint = vault_account.mint == mint.key() @ EscrowError::InvalidNftMint
-    )]
-    pub vault_account: Account<'info, TokenAccount>,
-    #[account(
-        init_if_needed,
-        payer = caller,
-        associated_token::mint = mint,
-        associated_token::authority = caller
-    )]
-    pub recipient_token_account: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+/// Returns every deposited NFT to its original depositor and closes its vault, sending
+/// the rent to whoever funded it.
+///
+/// Deposited NFTs are supplied in `remaining_accounts` as (mint, vault, depositor token account,
+/// vault funder) groups, initializer's first, then taker's, each in index order.
+pub fn return_deposited_nfts<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    remaining_accounts: &[AccountInfo<'info>],
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    // Compressed NFTs have no vault to return them from
+    require!(!escrow.holds_compressed(), EscrowError::CompressedNftsHeld);
+
+    // Collect every deposited NFT with its owner and vault funder: initializer's first, then taker's
+    let mut deposited_mints: Vec<(Pubkey, Pubkey, Pubkey)> = Vec::new();
+    for i in 0..escrow.initializer_nft_count as usize {
+        if escrow.initializer_nft_deposited[i] {
+            deposited_mints.push((
+                escrow.initializer_nft_mints[i],
+                escrow.initializer,
+                escrow.initializer_vault_funders[i],
+            ));
+        }
+    }
+    for i in 0..escrow.taker_nft_count as usize {
+        if escrow.taker_nft_deposited[i] {
+            deposited_mints.push((
+                escrow.taker_nft_mints[i],
+                escrow.taker,
+                escrow.taker_vault_funders[i],
+            ));
+        }
+    }
+
+    return_nfts(escrow, deposited_mints, remaining_accounts, token_program)
 }
 
-impl<'info> Complete<'info> {
-    pub fn close_escrow(&self) -> Result<()> {
-        // Transfer lamports from escrow account to initializer (rent return)
-        let escrow_starting_lamports = self.escrow_account.to_account_info().lamports();
-        **self.escrow_account.to_account_info().lamports.borrow_mut() = 0;
-        **self.initializer.to_account_info().lamports.borrow_mut() += escrow_starting_lamports;
-        
-        // Mark the account discriminator as closed
-        let escrow_account_info = self.escrow_account.to_account_info();
-        let mut escrow_data = escrow_account_info.data.borrow_mut();
-        escrow_data.fill(0);
-        
-        msg!("Escrow account closed. Rent returned to initializer: {} lamports", escrow_starting_lamports);
-        
-        Ok(())
+/// Returns each (mint, depositor, vault funder) in `nfts` to its depositor and closes its
+/// vault, sending the rent to whoever funded it. Each NFT is supplied in `remaining_accounts`
+/// as a (mint, vault, depositor token account, vault funder) group, in the same order.
+pub fn return_nfts<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    nfts: Vec<(Pubkey, Pubkey, Pubkey)>,
+    remaining_accounts: &[AccountInfo<'info>],
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    require!(
+        remaining_accounts.len() == nfts.len() * 4,
+        EscrowError::InvalidVaultAccounts
+    );
+
+    for (k, (expected_mint, depositor, funder)) in nfts.into_iter().enumerate() {
+        let mint_info = &remaining_accounts[k * 4];
+        let vault_info = &remaining_accounts[k * 4 + 1];
+        let depositor_token_info = &remaining_accounts[k * 4 + 2];
+        let funder_info = &remaining_accounts[k * 4 + 3];
+
+        require!(mint_info.key() == expected_mint, EscrowError::InvalidNftMint);
+        let mint = load_mint(mint_info, token_program.key)?;
+
+        let vault = load_token_account(vault_info, token_program.key)?;
+        require!(vault.mint == expected_mint, EscrowError::InvalidNftMint);
+        require!(vault.owner == escrow.key(), EscrowError::InvalidVaultAccounts);
+
+        let depositor_token = load_token_account(depositor_token_info, token_program.key)?;
+        require!(depositor_token.mint == expected_mint, EscrowError::InvalidNftMint);
+        require!(depositor_token.owner == depositor, EscrowError::InvalidRecipient);
+        require!(funder_info.key() == funder, EscrowError::InvalidRentRecipient);
+
+        // Return the NFT to the original depositor and the vault's rent to whoever funded it
+        transfer_from_vault(
+            escrow,
+            vault_info.clone(),
+            mint_info.clone(),
+            depositor_token_info.clone(),
+            token_program.clone(),
+            vault.amount,
+            mint.decimals,
+        )?;
+        close_vault(escrow, vault_info.clone(), funder_info.clone(), token_program.clone())?;
+
+        msg!("Returned NFT {} to depositor", expected_mint);
     }
+
+    Ok(())
 }
 
-#[derive(Accounts)]
-pub struct Cancel<'info> {
-    #[account(
-        mut,
-        constraint = escrow_account.is_initialized @ EscrowError::EscrowNotInitialized,
-        constraint = initializer.key() == escrow_account.initializer @ EscrowError::InvalidCanceller,
-        constraint = !escrow_account.initializer_deposited && !escrow_account.taker_deposited @ EscrowError::CannotCancelAfterDeposit,
-        close = initializer
-    )]
-    pub escrow_account: Account<'info, EscrowAccount>,
-    #[account(mut)]
-    pub initializer: Signer<'info>,
+/// Creates `recipient`'s ATA for `mint` if it does not exist yet. The recipient keeps the
+/// account, so it pays the rent and has to sign whenever one is created.
+pub fn create_recipient_token_account<'info>(
+    recipient: AccountInfo<'info>,
+    owner: AccountInfo<'info>,
+    token_account: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<()> {
+    if !token_account.data_is_empty() {
+        return Ok(());
+    }
+    require!(recipient.is_signer, EscrowError::RecipientMustFundTokenAccount);
+
+    let cpi_accounts = associated_token::Create {
+        payer: recipient.clone(),
+        associated_token: token_account,
+        authority: owner,
+        mint,
+        system_program,
+        token_program,
+    };
+    let cpi_ctx = CpiContext::new(associated_token_program, cpi_accounts);
+    associated_token::create_idempotent(cpi_ctx)
 }
 
-#[account]
-pub struct EscrowAccount {
+/// Delivers every uncollected NFT in both directions and marks the escrow completed.
+/// `remaining_accounts` holds the (mint, vault, recipient ATA, vault funder) groups for every
+/// uncollected taker NFT, then every uncollected initializer NFT, in index order.
+pub fn collect_both_sides<'info>(
+    escrow: &mut Account<'info, EscrowAccount>,
+    initializer: AccountInfo<'info>,
+    taker: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<()> {
+    let pending_taker: Vec<u8> =
+        (0..escrow.taker_nft_count).filter(|&i| !escrow.taker_nft_collected[i as usize]).collect();
+    let pending_initializer: Vec<u8> = (0..escrow.initializer_nft_count)
+        .filter(|&i| !escrow.initializer_nft_collected[i as usize])
+        .collect();
+    // Royalties need each NFT's metadata and creators, which only `complete` takes
+    require!(
+        pending_initializer.is_empty() || !escrow.royalties_due(),
+        EscrowError::RoyaltyAccountMissing
+    );
+    // Likewise for rechecking metadata fingerprinted at deposit time
+    require!(
+        !(escrow.block_on_metadata_change && escrow.tracks_metadata()),
+        EscrowError::MissingMetadataAccount
+    );
+    let split = pending_taker.len() * 4;
+    require!(remaining_accounts.len() >= split, EscrowError::InvalidVaultAccounts);
+
+    // Taker NFTs go to the initializer, initializer NFTs to the taker
+    collect_pending_nfts(
+        escrow,
+        true,
+        &pending_taker,
+        initializer.clone(),
+        &remaining_accounts[..split],
+        token_program.clone(),
+        associated_token_program.clone(),
+        system_program.clone(),
+    )?;
+    collect_pending_nfts(
+        escrow,
+        false,
+        &pending_initializer,
+        taker.clone(),
+        &remaining_accounts[split..],
+        token_program.clone(),
+        associated_token_program.clone(),
+        system_program.clone(),
+    )?;
+
+    escrow.taker_nft_collected.iter_mut().for_each(|c| *c = true);
+    escrow.initializer_nft_collected.iter_mut().for_each(|c| *c = true);
+    escrow.refresh_collection_status();
+    escrow.status = EscrowStatus::Completed;
+
+    Ok(())
+}
+
+/// The escrow's vault for `mint`: its associated token account under `token_program`
+pub fn escrow_vault_address(escrow: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    associated_token::get_associated_token_address_with_program_id(escrow, mint, token_program)
+}
+
+/// The asset id Bubblegum derives for the leaf minted with `nonce` into `merkle_tree`
+pub fn compressed_asset_id(merkle_tree: &Pubkey, nonce: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"asset", merkle_tree.as_ref(), &nonce.to_le_bytes()],
+        &BUBBLEGUM_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Moves a compressed NFT to `new_leaf_owner` with Bubblegum's `transfer`. `proof` is the
+/// leaf's proof path. The escrow signs for the leaf whenever it is the owner or delegate.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_compressed<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    tree_config: AccountInfo<'info>,
+    leaf_owner: AccountInfo<'info>,
+    leaf_delegate: AccountInfo<'info>,
+    new_leaf_owner: AccountInfo<'info>,
+    merkle_tree: AccountInfo<'info>,
+    log_wrapper: AccountInfo<'info>,
+    compression_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+    bubblegum_program: AccountInfo<'info>,
+    proof: &[AccountInfo<'info>],
+    leaf: &CompressedLeaf,
+) -> Result<()> {
+    let signs = |info: &AccountInfo| info.is_signer || info.key() == escrow.key();
+    let mut accounts = vec![
+        AccountMeta::new_readonly(tree_config.key(), false),
+        AccountMeta::new_readonly(leaf_owner.key(), signs(&leaf_owner)),
+        AccountMeta::new_readonly(leaf_delegate.key(), signs(&leaf_delegate)),
+        AccountMeta::new_readonly(new_leaf_owner.key(), false),
+        AccountMeta::new(merkle_tree.key(), false),
+        AccountMeta::new_readonly(log_wrapper.key(), false),
+        AccountMeta::new_readonly(compression_program.key(), false),
+        AccountMeta::new_readonly(system_program.key(), false),
+    ];
+    accounts.extend(proof.iter().map(|node| AccountMeta::new_readonly(node.key(), false)));
+
+    let mut data = BUBBLEGUM_TRANSFER_DISCRIMINATOR.to_vec();
+    leaf.serialize(&mut data)?;
+    let ix = Instruction {
+        program_id: BUBBLEGUM_PROGRAM_ID,
+        accounts,
+        data,
+    };
+
+    let mut infos = vec![
+        tree_config,
+        leaf_owner,
+        leaf_delegate,
+        new_leaf_owner,
+        merkle_tree,
+        log_wrapper,
+        compression_program,
+        system_program,
+        bubblegum_program,
+    ];
+    infos.extend_from_slice(proof);
+
+    escrow.with_signer_seeds(|signer| invoke_signed(&ix, &infos, signer))?;
+    Ok(())
+}
+
+/// Moves every NFT in `pending` out of its vault to `recipient` and closes the vault,
+/// sending the rent to whoever funded it. `is_initializer` selects the side being collected
+/// the same way it does for `complete`: true collects the taker's NFTs for the initializer.
+///
+/// Each pending NFT is supplied in `remaining_accounts` as (mint, vault, recipient ATA,
+/// vault funder), one group per pending index in index order. Every group is
+/// validated before the first transfer runs.
+#[allow(clippy::too_many_arguments)]
+pub fn collect_pending_nfts<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    is_initializer: bool,
+    pending: &[u8],
+    recipient: AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    token_program: AccountInfo<'info>,
+    associated_token_program: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<Vec<Pubkey>> {
+    require!(
+        remaining_accounts.len() == pending.len() * 4,
+        EscrowError::InvalidVaultAccounts
+    );
+
+    let (stored_mints, vault_funders) = if is_initializer {
+        (&escrow.taker_nft_mints, &escrow.taker_vault_funders)
+    } else {
+        (&escrow.initializer_nft_mints, &escrow.initializer_vault_funders)
+    };
+
+    let mut collected_mints = Vec::with_capacity(pending.len());
+    let mut mint_decimals = Vec::with_capacity(pending.len());
+
+    // First pass: check every group against the stored mints and funders without moving anything
+    for (k, &nft_index) in pending.iter().enumerate() {
+        let accounts = &remaining_accounts[k * 4..(k + 1) * 4];
+        let expected_mint = stored_mints[nft_index as usize];
+        require!(!escrow.is_compressed(!is_initializer, nft_index), EscrowError::CompressedNft);
+
+        require!(accounts[0].key() == expected_mint, EscrowError::InvalidNftMint);
+        let mint = load_mint(&accounts[0], token_program.key)?;
+
+        require!(
+            accounts[1].key() == escrow_vault_address(&escrow.key(), &expected_mint, token_program.key),
+            EscrowError::InvalidVaultAccounts
+        );
+        let vault = load_token_account(&accounts[1], token_program.key)?;
+        require!(vault.mint == expected_mint, EscrowError::InvalidNftMint);
+        require!(vault.owner == escrow.key(), EscrowError::InvalidVaultAccounts);
+        require!(vault.amount >= escrow.nft_amount(!is_initializer, nft_index), EscrowError::VaultEmpty);
+        require!(!vault.is_frozen(), EscrowError::TokenAccountFrozen);
+
+        require!(
+            accounts[3].key() == vault_funders[nft_index as usize],
+            EscrowError::InvalidRentRecipient
+        );
+
+        collected_mints.push(expected_mint);
+        mint_decimals.push(mint.decimals);
+    }
+
+    // Second pass: every group checked out, so deliver the NFTs and close the vaults
+    let delivery_owner = escrow.delivery_owner(is_initializer);
+    for (k, expected_mint) in collected_mints.iter().enumerate() {
+        let accounts = &remaining_accounts[k * 4..(k + 1) * 4];
+        let mint_info = &accounts[0];
+        let vault_info = &accounts[1];
+        let recipient_token_info = &accounts[2];
+        let funder_info = &accounts[3];
+
+        require!(
+            recipient_token_info.key()
+                == associated_token::get_associated_token_address_with_program_id(
+                    &delivery_owner,
+                    expected_mint,
+                    token_program.key,
+                ),
+            EscrowError::NotAssociatedTokenAccount
+        );
+        // Only the recipient's own ATA can be created here; one at a delivery address must
+        // already exist
+        if delivery_owner == recipient.key() {
+            create_recipient_token_account(
+                recipient.clone(),
+                recipient.clone(),
+                recipient_token_info.clone(),
+                mint_info.clone(),
+                token_program.clone(),
+                associated_token_program.clone(),
+                system_program.clone(),
+            )?;
+        } else {
+            require!(!recipient_token_info.data_is_empty(), EscrowError::RecipientMustFundTokenAccount);
+        }
+        let recipient_token = load_token_account(recipient_token_info, token_program.key)?;
+        require!(recipient_token.mint == *expected_mint, EscrowError::InvalidNftMint);
+        require!(recipient_token.owner == delivery_owner, EscrowError::InvalidRecipient);
+        require!(!recipient_token.is_frozen(), EscrowError::TokenAccountFrozen);
+
+        transfer_from_vault(
+            escrow,
+            vault_info.clone(),
+            mint_info.clone(),
+            recipient_token_info.clone(),
+            token_program.clone(),
+            escrow.nft_amount(!is_initializer, pending[k]),
+            mint_decimals[k],
+        )?;
+        close_vault(escrow, vault_info.clone(), funder_info.clone(), token_program.clone())?;
+
+        msg!("Transferred NFT {} from escrow vault to recipient", expected_mint);
+    }
+
+    Ok(collected_mints)
+}
+
+/// Pays the taker's SOL out of the escrow account: the protocol fee to `fee_recipient`
+/// and the rest to the initializer.
+pub fn release_sol<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    fee_recipient: AccountInfo<'info>,
+    initializer: AccountInfo<'info>,
+) -> Result<()> {
+    let sol_amount = escrow.sol_amount;
+    if sol_amount == 0 {
+        return Ok(());
+    }
+
+    // Both parties' shares of the protocol fee go out first: the taker's was escrowed on top
+    // of the payment, the maker's comes out of it
+    let maker_fee = escrow.maker_fee_amount();
+    let fee = maker_fee + escrow.taker_fee_amount();
+    if fee > 0 {
+        **escrow.to_account_info().lamports.borrow_mut() -= fee;
+        **fee_recipient.lamports.borrow_mut() += fee;
+
+        msg!("Protocol fee paid to {}: {} lamports", fee_recipient.key(), fee);
+    }
+
+    // Transfer the rest of the taker's SOL payment from the escrow account to the initializer,
+    // less any royalties already paid to creators
+    let initializer_amount = sol_amount - maker_fee - escrow.royalties_paid;
+    **escrow.to_account_info().lamports.borrow_mut() -= initializer_amount;
+    **initializer.lamports.borrow_mut() += initializer_amount;
+
+    msg!("SOL payment released to initializer: {} lamports", initializer_amount);
+
+    Ok(())
+}
+
+/// Adds `escrow` to a user's list, growing the account with `payer` covering the extra rent
+pub fn list_escrow<'info>(
+    list: &mut Account<'info, UserEscrowList>,
+    escrow: Pubkey,
+    payer: AccountInfo<'info>,
+    system_program: AccountInfo<'info>,
+) -> Result<()> {
+    require!(list.escrows.len() < MAX_LISTED_ESCROWS, EscrowError::EscrowListFull);
+
+    let needed = 8 + UserEscrowList::space(list.escrows.len() + 1);
+    if list.to_account_info().data_len() < needed {
+        resize_escrow(list.to_account_info(), payer, system_program, needed)?;
+    }
+    list.escrows.push(escrow);
+
+    Ok(())
+}
+
+/// Removes a closing escrow from the parties' lists. A list the escrow was recorded in must be
+/// passed so no stale entry outlives it; others are cleaned up if passed. The accounts keep
+/// their size so the freed space is reused by the next escrow.
+pub fn unlist_escrow(
+    escrow: &Account<EscrowAccount>,
+    initializer_list: Option<&mut Account<UserEscrowList>>,
+    taker_list: Option<&mut Account<UserEscrowList>>,
+) -> Result<()> {
+    require!(
+        !escrow.listed_initializer || initializer_list.is_some(),
+        EscrowError::MissingEscrowList
+    );
+    require!(!escrow.listed_taker || taker_list.is_some(), EscrowError::MissingEscrowList);
+
+    let key = escrow.key();
+    for list in [initializer_list, taker_list].into_iter().flatten() {
+        list.escrows.retain(|listed| *listed != key);
+    }
+
+    Ok(())
+}
+
+/// Counts a new escrow against its initializer, failing once the configured cap is reached
+pub fn open_active_slot(
+    stats: &mut Account<InitializerStats>,
+    initializer: Pubkey,
+    bump: u8,
+    max_active_escrows: u16,
+) -> Result<()> {
+    // First escrow for this initializer: the counter was just created
+    if stats.initializer == Pubkey::default() {
+        stats.initializer = initializer;
+        stats.bump = bump;
+    }
+
+    require!(
+        max_active_escrows == 0 || stats.active_escrows < max_active_escrows,
+        EscrowError::TooManyActiveEscrows
+    );
+    stats.active_escrows = stats
+        .active_escrows
+        .checked_add(1)
+        .ok_or(EscrowError::TooManyActiveEscrows)?;
+
+    Ok(())
+}
+
+/// Releases this escrow's slot in its initializer's counter as it reaches a terminal state.
+/// Escrows created before the counter existed were never counted and need no stats account.
+pub fn release_active_slot(
+    escrow: &EscrowAccount,
+    stats: Option<&mut Account<InitializerStats>>,
+) -> Result<()> {
+    if !escrow.counted_as_active {
+        return Ok(());
+    }
+
+    let stats = stats.ok_or(EscrowError::MissingInitializerStats)?;
+    stats.active_escrows = stats.active_escrows.saturating_sub(1);
+
+    Ok(())
+}
+
+/// Closes the escrow account into `rent_payer`, who funded it. Returns the lamports sent back.
+pub fn close_escrow<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    rent_payer: AccountInfo<'info>,
+) -> Result<u64> {
+    require!(rent_payer.key() == escrow.rent_payer, EscrowError::InvalidRentRecipient);
+
+    // Transfer the remaining lamports from the escrow account to its rent payer
+    let escrow_starting_lamports = escrow.to_account_info().lamports();
+
+    // Anchor's close hands the account back to the system program with no data and no
+    // lamports, so it cannot be revived later in the same transaction and is skipped on exit.
+    // Any later instruction that loads it as an escrow fails with AccountNotInitialized
+    // instead of reading zeroed data.
+    escrow.close(rent_payer)?;
+
+    msg!("Escrow account closed. Rent returned to {}: {} lamports", escrow.rent_payer, escrow_starting_lamports);
+
+    Ok(escrow_starting_lamports)
+}
+
+/// Closes empty escrow-owned vaults that outlived their deposits, sending each vault's rent
+/// to whoever funded it. A vault the escrow has no funder on record for was created by
+/// someone else, so it is refused rather than handing its rent to either party.
+///
+/// Vaults are supplied in `remaining_accounts` as (vault, rent recipient) pairs.
+pub fn close_stray_vaults<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    remaining_accounts: &[AccountInfo<'info>],
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    require!(remaining_accounts.len() % 2 == 0, EscrowError::InvalidVaultAccounts);
+
+    for pair in remaining_accounts.chunks(2) {
+        let vault_info = &pair[0];
+        let rent_recipient_info = &pair[1];
+
+        let vault = load_token_account(vault_info, token_program.key)?;
+        require!(vault.owner == escrow.key(), EscrowError::InvalidVaultAccounts);
+        require!(vault.amount == 0, EscrowError::InvalidVaultAccounts);
+
+        // Look the vault's mint up on either side to find who paid for it
+        let funder = escrow
+            .initializer_nft_mints
+            .iter()
+            .zip(escrow.initializer_vault_funders.iter())
+            .chain(escrow.taker_nft_mints.iter().zip(escrow.taker_vault_funders.iter()))
+            .find(|(mint, funder)| **mint == vault.mint && **funder != Pubkey::default())
+            .map(|(_, funder)| *funder)
+            .ok_or(EscrowError::UnknownVaultFunder)?;
+        require!(rent_recipient_info.key() == funder, EscrowError::InvalidRentRecipient);
+
+        close_vault(escrow, vault_info.clone(), rent_recipient_info.clone(), token_program.clone())?;
+
+        msg!("Closed stray vault {} for mint {}", vault_info.key(), vault.mint);
+    }
+
+    Ok(())
+}
+
+/// Moves the escrowed token leg to `recipient` and closes its vault, returning the rent to
+/// whoever funded it. Does nothing if the leg was never deposited; otherwise every account
+/// must be supplied.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_token_leg<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    recipient: Pubkey,
+    mint: Option<&InterfaceAccount<'info, Mint>>,
+    vault: Option<&InterfaceAccount<'info, TokenAccount>>,
+    destination: Option<&InterfaceAccount<'info, TokenAccount>>,
+    vault_funder: Option<AccountInfo<'info>>,
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    if !escrow.token_deposited {
+        return Ok(());
+    }
+
+    let (mint, vault, destination, vault_funder) = match (mint, vault, destination, vault_funder) {
+        (Some(mint), Some(vault), Some(destination), Some(vault_funder)) => {
+            (mint, vault, destination, vault_funder)
+        }
+        _ => return err!(EscrowError::MissingTokenLegAccounts),
+    };
+
+    require!(mint.key() == escrow.token_mint, EscrowError::InvalidTokenAccount);
+    require!(vault.mint == escrow.token_mint, EscrowError::InvalidTokenAccount);
+    require!(vault.owner == escrow.key(), EscrowError::InvalidVaultAuthority);
+    require!(destination.mint == escrow.token_mint, EscrowError::InvalidTokenAccount);
+    require!(destination.owner == recipient, EscrowError::InvalidRecipient);
+    require!(vault_funder.key() == escrow.token_vault_funder, EscrowError::InvalidRentRecipient);
+
+    transfer_from_vault(
+        escrow,
+        vault.to_account_info(),
+        mint.to_account_info(),
+        destination.to_account_info(),
+        token_program.clone(),
+        vault.amount,
+        mint.decimals,
+    )?;
+    close_vault(escrow, vault.to_account_info(), vault_funder, token_program)?;
+
+    msg!("Released {} of token {} to {}", vault.amount, escrow.token_mint, recipient);
+
+    Ok(())
+}
+
+/// Transfers `amount` tokens out of an escrow-owned vault, signing as the escrow PDA.
+pub fn transfer_from_vault<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    vault: AccountInfo<'info>,
+    mint: AccountInfo<'info>,
+    to: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+    amount: u64,
+    decimals: u8,
+) -> Result<()> {
+    let cpi_accounts = token_interface::TransferChecked {
+        from: vault,
+        mint,
+        to,
+        authority: escrow.to_account_info(),
+    };
+
+    escrow.with_signer_seeds(|signer| {
+        let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
+        token_interface::transfer_checked(cpi_ctx, amount, decimals)
+    })
+}
+
+/// Closes an empty escrow-owned vault, sending its rent to `destination`.
+pub fn close_vault<'info>(
+    escrow: &Account<'info, EscrowAccount>,
+    vault: AccountInfo<'info>,
+    destination: AccountInfo<'info>,
+    token_program: AccountInfo<'info>,
+) -> Result<()> {
+    let cpi_accounts = token_interface::CloseAccount {
+        account: vault,
+        destination,
+        authority: escrow.to_account_info(),
+    };
+
+    escrow.with_signer_seeds(|signer| {
+        let cpi_ctx = CpiContext::new_with_signer(token_program, cpi_accounts, signer);
+        token_interface::close_account(cpi_ctx)
+    })
+}
+
+/// Transfers from `payer` whatever the escrow needs to be rent-exempt at `space` bytes,
+/// not counting `reserved_lamports` held for the swap itself. Returns the amount sent.
+pub fn top_up_rent<'info>(
+    escrow: AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    system_program_info: AccountInfo<'info>,
+    space: usize,
+    reserved_lamports: u64,
+) -> Result<u64> {
+    let rent_exempt_minimum = Rent::get()?.minimum_balance(space);
+    let rent_lamports = escrow.lamports().saturating_sub(reserved_lamports);
+    if rent_exempt_minimum <= rent_lamports {
+        return Ok(0);
+    }
+
+    let shortfall = rent_exempt_minimum - rent_lamports;
+    let cpi_accounts = system_program::Transfer {
+        from: payer,
+        to: escrow,
+    };
+    let cpi_ctx = CpiContext::new(system_program_info, cpi_accounts);
+    system_program::transfer(cpi_ctx, shortfall)?;
+
+    Ok(shortfall)
+}
+
+/// Reallocates the escrow account to `new_space` bytes, with `payer` topping up
+/// the rent-exempt balance when the account grows. Only used before any SOL is escrowed.
+pub fn resize_escrow<'info>(
+    escrow: AccountInfo<'info>,
+    payer: AccountInfo<'info>,
+    system_program_info: AccountInfo<'info>,
+    new_space: usize,
+) -> Result<()> {
+    top_up_rent(escrow.clone(), payer, system_program_info, new_space, 0)?;
+
+    escrow.realloc(new_space, false)?;
+
+    Ok(())
+}
+
+/// Deserializes a token account passed through `remaining_accounts`.
+pub fn load_token_account(info: &AccountInfo, token_program: &Pubkey) -> Result<TokenAccount> {
+    require!(info.owner == token_program, EscrowError::InvalidTokenAccount);
+    let data = info.try_borrow_data()?;
+    TokenAccount::try_deserialize(&mut &data[..])
+}
+
+/// Fingerprints the parts of an NFT's metadata that describe it: name, symbol, URI, royalty
+/// and creators.
+pub fn hash_metadata(metadata: &MetadataAccount) -> [u8; 32] {
+    let mut data = Vec::new();
+    for field in [&metadata.name, &metadata.symbol, &metadata.uri] {
+        data.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        data.extend_from_slice(field.as_bytes());
+    }
+    data.extend_from_slice(&metadata.seller_fee_basis_points.to_le_bytes());
+    for creator in metadata.creators.iter().flatten() {
+        data.extend_from_slice(creator.address.as_ref());
+        data.push(creator.verified as u8);
+        data.push(creator.share);
+    }
+    solana_program::hash::hash(&data).to_bytes()
+}
+
+/// Deserializes a Metaplex metadata account passed through `remaining_accounts`.
+pub fn load_metadata(info: &AccountInfo) -> Result<MetadataAccount> {
+    require!(*info.owner == MetadataAccount::owner(), EscrowError::InvalidMetadataAccount);
+    let data = info.try_borrow_data()?;
+    MetadataAccount::try_deserialize(&mut &data[..])
+}
+
+/// Deserializes a mint passed through `remaining_accounts`.
+pub fn load_mint(info: &AccountInfo, token_program: &Pubkey) -> Result<Mint> {
+    require!(info.owner == token_program, EscrowError::InvalidNftMint);
+    let data = info.try_borrow_data()?;
+    Mint::try_deserialize(&mut &data[..])
+}
+
+#[event]
+pub struct TimeoutExtended {
+    pub escrow: Pubkey,
+    pub additional_seconds: i64,
+    pub expires_at: i64,
+}
+
+/// Emitted by `reconcile` when the stored aggregate flags disagreed with the per-NFT arrays.
+#[event]
+pub struct FlagsReconciled {
+    pub escrow: Pubkey,
+    pub before: AggregateFlags,
+    pub after: AggregateFlags,
+}
+
+#[event]
+pub struct EscrowRejected {
+    pub escrow: Pubkey,
     pub initializer: Pubkey,
     pub taker: Pubkey,
-    pub initializer_nft_count: u8,
-    pub taker_nft_count: u8,
-    pub initializer_nft_mints: [Pubkey; 3],
-    pub taker_nft_mints: [Pubkey; 3],
-    pub initializer_nft_deposited: [bool; 3],
-    pub taker_nft_deposited: [bool; 3],
-    pub initializer_nft_collected: [bool; 3],
-    pub taker_nft_collected: [bool; 3],
-    pub initializer_deposited: bool,
-    pub taker_deposited: bool,
-    pub initializer_collected: bool,
-    pub taker_collected: bool,
-    pub is_initialized: bool,
-    pub bump: u8,
+}
+
+/// Emitted for every NFT moved out of escrow by `complete`; `collector` is the party that
+/// triggered it.
+#[event]
+pub struct NftCollected {
+    pub escrow: Pubkey,
+    pub collector: Pubkey,
+    pub recipient: Pubkey,
+    pub mint: Pubkey,
+    pub nft_index: u8,
+    pub collected_at: i64,
+}
+
+/// Emitted when an escrow or open offer is created. For an open offer `taker` is the default
+/// key until someone claims it.
+#[event]
+pub struct EscrowInitialized {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub nonce: u64,
+    pub memo: [u8; 32],
     pub created_at: i64,
-    pub timeout_in_seconds: i64,
 }
 
-impl EscrowAccount {
-    pub fn space() -> usize {
-        8 +  // discriminator
-        32 + // initializer
-        32 + // taker
-        1 +  // initializer_nft_count
-        1 +  // taker_nft_count
-        (32 * 3) + // initializer_nft_mints
-        (32 * 3) + // taker_nft_mints
-        (1 * 3) + // initializer_nft_deposited
-        (1 * 3) + // taker_nft_deposited
-        (1 * 3) + // initializer_nft_collected
-        (1 * 3) + // taker_nft_collected
-        1 +  // initializer_deposited
-        1 +  // taker_deposited
-        1 +  // initializer_collected
-        1 +  // taker_collected
-        1 +  // is_initialized
-        1 +  // bump
-        8 +  // created_at
-        8    // timeout_in_seconds
-    }
+/// Emitted for every NFT that lands in an escrow vault.
+#[event]
+pub struct NftDeposited {
+    pub escrow: Pubkey,
+    pub depositor: Pubkey,
+    pub mint: Pubkey,
+    pub is_initializer: bool,
+    pub nft_index: u8,
+    pub deposited_at: i64,
+}
+
+/// Emitted whenever a deposit or withdrawal changes which NFTs are in the escrow, with both
+/// sides' running totals, so a UI can show progress without replaying the `NftDeposited`
+/// events.
+#[event]
+pub struct DepositProgress {
+    pub escrow: Pubkey,
+    pub initializer_deposited: u8,
+    pub initializer_total: u8,
+    pub taker_deposited: u8,
+    pub taker_total: u8,
+}
+
+/// Emitted by `complete` when an NFT's metadata no longer matches its fingerprint from
+/// deposit time.
+#[event]
+pub struct MetadataChanged {
+    pub escrow: Pubkey,
+    pub mint: Pubkey,
+    pub nft_index: u8,
+    pub deposited_hash: [u8; 32],
+    pub current_hash: [u8; 32],
+}
+
+/// Emitted when reported completion failures pause the program.
+#[event]
+pub struct CircuitBreakerTripped {
+    pub failure_count: u16,
+    pub window_start: i64,
+    pub tripped_at: i64,
+}
+
+/// The full record of a completed swap, emitted once when the escrow is closed by `finalize`,
+/// `settle` or `arbiter_settle`.
+#[event]
+pub struct EscrowSettled {
+    pub escrow: Pubkey,
+    pub initializer: Pubkey,
+    pub taker: Pubkey,
+    pub initializer_nft_mints: Vec<Pubkey>,
+    pub taker_nft_mints: Vec<Pubkey>,
+    pub sol_amount: u64,
+    pub rent_returned: u64,
+    pub created_at: i64,
+    pub settled_at: i64,
 }
 
 #[error_code]
 pub enum EscrowError {
-    #[msg("NFT count must be between 1 and 3.")]
+    #[msg("NFT count must be between 1 and 20.")]
     InvalidNftCount,
     #[msg("Escrow not initialized.")]
     EscrowNotInitialized,
@@ -526,4 +6537,168 @@ pub enum EscrowError {
     InvalidRecipient,
     #[msg("Escrow can only be canceled by the initializer.")]
     InvalidCanceller,
+    #[msg("Timeout must be between 5 minutes and 30 days.")]
+    InvalidTimeout,
+    #[msg("This NFT has not been deposited.")]
+    NftNotDeposited,
+    #[msg("Cannot withdraw after the counterparty has deposited all their NFTs.")]
+    CounterpartyAlreadyDeposited,
+    #[msg("Insufficient SOL to cover the required payment.")]
+    InsufficientSolDeposit,
+    #[msg("This escrow does not require a SOL payment.")]
+    SolPaymentNotRequired,
+    #[msg("Fee cannot exceed 1000 basis points.")]
+    FeeTooHigh,
+    #[msg("Initializer and taker must be different accounts.")]
+    SelfEscrowNotAllowed,
+    #[msg("Number of mint accounts supplied does not match the NFT counts.")]
+    MissingMintAccounts,
+    #[msg("The same mint cannot be listed more than once in an escrow.")]
+    DuplicateMint,
+    #[msg("Token is not an NFT. Expected decimals 0 and supply 1.")]
+    NotAnNft,
+    #[msg("NFT does not belong to the expected collection.")]
+    CollectionMismatch,
+    #[msg("NFT collection is not verified.")]
+    CollectionNotVerified,
+    #[msg("A metadata account is required to verify this NFT.")]
+    MissingMetadataAccount,
+    #[msg("Metadata account does not belong to this NFT mint.")]
+    InvalidMetadataAccount,
+    #[msg("Both the initializer and the taker must sign.")]
+    BothPartiesMustSign,
+    #[msg("Vault accounts supplied do not match the deposited NFTs.")]
+    InvalidVaultAccounts,
+    #[msg("Operation not allowed in the current escrow status.")]
+    InvalidEscrowStatus,
+    #[msg("Cannot extend the timeout of an escrow that has already expired.")]
+    CannotExtendExpiredEscrow,
+    #[msg("Timeout extension exceeds the maximum of 30 days.")]
+    TimeoutExtensionTooLarge,
+    #[msg("Rent recipient does not match the account that funded the vault.")]
+    InvalidRentRecipient,
+    #[msg("Timeout arithmetic overflowed.")]
+    TimeoutOverflow,
+    #[msg("Vault account is not owned by the escrow.")]
+    InvalidVaultAuthority,
+    #[msg("This escrow is not an open offer.")]
+    NotAnOpenOffer,
+    #[msg("This open offer has already been claimed.")]
+    OfferAlreadyClaimed,
+    #[msg("Only the taker can reject the escrow.")]
+    OnlyTakerCanReject,
+    #[msg("Only the taker can propose a counter-offer.")]
+    OnlyTakerCanCounter,
+    #[msg("Cannot propose a counter-offer after deposits have been made.")]
+    CannotCounterAfterDeposit,
+    #[msg("A counter-offer is awaiting the initializer's acceptance.")]
+    CounterPending,
+    #[msg("There is no counter-offer to accept.")]
+    NoCounterPending,
+    #[msg("Both parties must confirm they are ready before completion.")]
+    PartiesNotReady,
+    #[msg("Escrow has not expired yet.")]
+    EscrowNotExpired,
+    #[msg("Escrow account is already on the current layout.")]
+    AccountAlreadyCurrent,
+    #[msg("Escrow account does not match a known legacy layout.")]
+    InvalidLegacyAccount,
+    #[msg("Signer is neither the depositing party nor its registered delegate.")]
+    UnauthorizedDelegate,
+    #[msg("Token account is frozen.")]
+    TokenAccountFrozen,
+    #[msg("The program is paused.")]
+    ProgramPaused,
+    #[msg("Only the config admin can perform this action.")]
+    NotAdmin,
+    #[msg("NFT does not belong to any of the allowed collections.")]
+    MintNotInWhitelist,
+    #[msg("Too many allowed taker collections.")]
+    TooManyAllowedCollections,
+    #[msg("Token leg accounts are required to move the escrowed tokens.")]
+    MissingTokenLegAccounts,
+    #[msg("A completion is already in progress for this escrow.")]
+    CompletionInProgress,
+    #[msg("Cannot change the taker after deposits have been made.")]
+    CannotChangeTakerAfterDeposit,
+    #[msg("NFT metadata does not match the agreed symbol.")]
+    MetadataMismatch,
+    #[msg("The recipient must sign to pay for their new token account.")]
+    RecipientMustFundTokenAccount,
+    #[msg("The deposit deadline for this escrow has passed.")]
+    DepositWindowClosed,
+    #[msg("The escrow vault for this NFT is empty.")]
+    VaultEmpty,
+    #[msg("Cannot deposit once collection of the escrow has begun.")]
+    CannotDepositDuringSettlement,
+    #[msg("The escrow can only be reaped after its timeout if the taker deposited nothing.")]
+    NotReapable,
+    #[msg("The token program does not match the one this escrow was created with.")]
+    TokenProgramMismatch,
+    #[msg("The recipient token account is not the recipient's associated token account.")]
+    NotAssociatedTokenAccount,
+    #[msg("This escrow has no arbiter.")]
+    NoArbiter,
+    #[msg("Only the escrow's arbiter can do this.")]
+    NotArbiter,
+    #[msg("The SOL deposit deadline has passed; the initializer must re-quote.")]
+    SolQuoteExpired,
+    #[msg("A metadata or creator account needed to pay royalties is missing.")]
+    RoyaltyAccountMissing,
+    #[msg("Initializer already has the maximum number of active escrows.")]
+    TooManyActiveEscrows,
+    #[msg("The initializer's active escrow counter must be passed to close this escrow.")]
+    MissingInitializerStats,
+    #[msg("Cannot drop a taker slot whose NFT is deposited; withdraw it first.")]
+    CannotRemoveDepositedSlot,
+    #[msg("The escrow list is full.")]
+    EscrowListFull,
+    #[msg("Cannot transfer the initializer's position after deposits have been made.")]
+    CannotTransferAfterDeposit,
+    #[msg("The swap can still be completed; nothing owed is missing from its vault.")]
+    SwapUnfulfillable,
+    #[msg("Only the config admin or pauser can pause the program.")]
+    NotPauser,
+    #[msg("NFT metadata changed since it was deposited.")]
+    MetadataChangedSinceDeposit,
+    #[msg("The same mint is listed on both sides of the swap.")]
+    CrossSideDuplicateMint,
+    #[msg("Only the parties may act until the keeper grace period has passed.")]
+    KeeperGracePeriodActive,
+    #[msg("The token account has an active delegate.")]
+    TokenAccountHasDelegate,
+    #[msg("This NFT was not deposited as a compressed NFT.")]
+    NotCompressedNft,
+    #[msg("Compressed NFTs are moved with the compressed instructions.")]
+    CompressedNft,
+    #[msg("Withdraw compressed NFTs with withdraw_compressed first.")]
+    CompressedNftsHeld,
+    #[msg("The cluster clock returned a non-positive timestamp.")]
+    InvalidClock,
+    #[msg("The token program is not on the config allowlist.")]
+    TokenProgramNotAllowed,
+    #[msg("Too many token programs in the allowlist.")]
+    TooManyTokenPrograms,
+    #[msg("Neither side of the escrow provides any NFTs.")]
+    EmptyBothSides,
+    #[msg("The escrow has no record of who funded this vault.")]
+    UnknownVaultFunder,
+    #[msg("The escrow has not been approved for deposits yet.")]
+    EscrowNotApproved,
+    #[msg("Only the escrow's approver can approve it.")]
+    NotApprover,
+    #[msg("NFT amounts must list one non-zero amount per slot.")]
+    InvalidNftAmounts,
+    #[msg("Only the failure reporter or admin can report completion failures.")]
+    NotFailureReporter,
+    #[msg("Failure window must be positive when a threshold is set.")]
+    InvalidFailureWindow,
+    #[msg("Delivery addresses cannot change once anything is deposited.")]
+    DeliveryAddressLocked,
+    #[msg("An escrow can only be added to a list whose owner signs.")]
+    EscrowListOwnerMustSign,
+    #[msg("The escrow list this escrow is recorded in must be passed.")]
+    MissingEscrowList,
+    #[msg("Royalty arithmetic overflowed.")]
+    RoyaltyOverflow,
 }
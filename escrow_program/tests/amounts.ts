@@ -0,0 +1,78 @@
+import {
+  createMint,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { expect } from "chai";
+import {
+  Party,
+  completeCall,
+  confirmReady,
+  createParty,
+  deposit,
+  depositCall,
+  expectEscrowError,
+  initializeEscrow,
+  provider,
+} from "./utils";
+
+/** Gives `party` a fresh zero-decimal mint with `supply` tokens in its own account. */
+async function addSemiFungible(party: Party, supply: number): Promise<void> {
+  const { keypair } = party;
+  const mint = await createMint(
+    provider.connection,
+    keypair,
+    keypair.publicKey,
+    null,
+    0
+  );
+  const ata = await getOrCreateAssociatedTokenAccount(
+    provider.connection,
+    keypair,
+    mint,
+    keypair.publicKey
+  );
+  await mintTo(provider.connection, keypair, mint, ata.address, keypair, supply);
+  party.mints.push(mint);
+}
+
+describe("per-slot amounts", () => {
+  it("escrows and delivers the agreed quantity of a semi-fungible token", async () => {
+    const initializer = await createParty(0);
+    await addSemiFungible(initializer, 5);
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker, {
+      nftAmounts: [5, 1],
+    });
+
+    await deposit(escrow, initializer, true, 0);
+    await deposit(escrow, taker, false, 0);
+    await confirmReady(escrow, initializer);
+    await confirmReady(escrow, taker);
+
+    const funded = { escrow, initializer, taker };
+    await completeCall(funded, false, 0).rpc();
+
+    const received = await getAccount(
+      provider.connection,
+      getAssociatedTokenAddressSync(initializer.mints[0], taker.keypair.publicKey)
+    );
+    expect(Number(received.amount)).to.equal(5);
+  });
+
+  it("rejects a deposit holding a different quantity than the slot asks for", async () => {
+    const initializer = await createParty(0);
+    await addSemiFungible(initializer, 5);
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker, {
+      nftAmounts: [3, 1],
+    });
+
+    await expectEscrowError(
+      depositCall(escrow, initializer, true, 0).rpc(),
+      "InvalidTokenAmount"
+    );
+  });
+});
@@ -0,0 +1,52 @@
+import { Keypair } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  airdrop,
+  createParty,
+  deposit,
+  depositCall,
+  expectEscrowError,
+  initializeEscrow,
+  program,
+} from "./utils";
+
+describe("approval", () => {
+  it("holds deposits until the approver signs off", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const approver = Keypair.generate();
+    await airdrop(approver.publicKey, 1);
+    const escrow = await initializeEscrow(initializer, taker, {
+      approver: approver.publicKey,
+    });
+
+    await expectEscrowError(
+      depositCall(escrow, initializer, true, 0).rpc(),
+      "EscrowNotApproved"
+    );
+
+    // Neither party can approve its own escrow
+    await expectEscrowError(
+      program.methods
+        .approve()
+        .accounts({
+          approver: initializer.keypair.publicKey,
+          escrowAccount: escrow,
+        })
+        .signers([initializer.keypair])
+        .rpc(),
+      "NotApprover"
+    );
+
+    await program.methods
+      .approve()
+      .accounts({ approver: approver.publicKey, escrowAccount: escrow })
+      .signers([approver])
+      .rpc();
+
+    await deposit(escrow, initializer, true, 0);
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.approved).to.be.true;
+    expect(account.initializerNftDeposited).to.deep.equal([true]);
+  });
+});
@@ -0,0 +1,57 @@
+import { PublicKey } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  Party,
+  createParty,
+  deposit,
+  expectEscrowError,
+  initializeEscrow,
+  initializerStatsPda,
+  program,
+} from "./utils";
+
+function cancelCall(escrow: PublicKey, initializer: Party) {
+  return program.methods
+    .cancel()
+    .accounts({
+      escrowAccount: escrow,
+      initializer: initializer.keypair.publicKey,
+      rentPayer: initializer.keypair.publicKey,
+      initializerStats: initializerStatsPda(initializer.keypair.publicKey),
+      initializerEscrowList: null,
+      takerEscrowList: null,
+      taker: null,
+      tokenLegMint: null,
+      tokenLegVault: null,
+      tokenLegDestination: null,
+      tokenLegVaultFunder: null,
+      tokenProgram: null,
+    })
+    .signers([initializer.keypair]);
+}
+
+describe("cancel", () => {
+  it("closes an escrow nothing has been deposited into", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker);
+
+    await cancelCall(escrow, initializer).rpc();
+    expect(await program.account.escrowAccount.fetchNullable(escrow)).to.be
+      .null;
+  });
+
+  it("refuses once an NFT sits in a vault", async () => {
+    const initializer = await createParty(2);
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker);
+    await deposit(escrow, initializer, true, 0);
+
+    await expectEscrowError(
+      cancelCall(escrow, initializer).rpc(),
+      "CannotCancelAfterDeposit"
+    );
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.initializerNftDeposited).to.deep.equal([true, false]);
+  });
+});
@@ -0,0 +1,101 @@
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
+import { ComputeBudgetProgram, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  FundedEscrow,
+  configPda,
+  expectEscrowError,
+  program,
+  provider,
+  setupFundedEscrow,
+  vaultFor,
+} from "./utils";
+
+/** Builds a `complete_all` call collecting `count` of the taker's NFTs from `startIndex`. */
+function completeAllCall(
+  funded: FundedEscrow,
+  startIndex: number,
+  count: number
+) {
+  const initializer = funded.initializer.keypair.publicKey;
+  const mints = funded.taker.mints.slice(startIndex, startIndex + count);
+
+  return program.methods
+    .completeAll(true, startIndex, count)
+    .accounts({
+      caller: initializer,
+      escrowAccount: funded.escrow,
+      config: configPda,
+      recipient: initializer,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    })
+    .remainingAccounts(
+      mints.flatMap((mint) => [
+        { pubkey: mint, isSigner: false, isWritable: false },
+        {
+          pubkey: vaultFor(funded.escrow, mint),
+          isSigner: false,
+          isWritable: true,
+        },
+        {
+          pubkey: getAssociatedTokenAddressSync(mint, initializer),
+          isSigner: false,
+          isWritable: true,
+        },
+        {
+          pubkey: funded.taker.keypair.publicKey,
+          isSigner: false,
+          isWritable: true,
+        },
+      ])
+    )
+    .preInstructions([
+      ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 }),
+    ])
+    .signers([funded.initializer.keypair]);
+}
+
+describe("chunked complete_all", () => {
+  it("collects a large bundle over several transactions", async () => {
+    const funded = await setupFundedEscrow(1, 10);
+
+    // Three slices of at most SAFE_CHUNK_SIZE: 0..4, 4..8, 8..10
+    await completeAllCall(funded, 0, 4).rpc();
+    let account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.takerNftCollected.filter((c) => c)).to.have.length(4);
+    expect(account.takerCollected).to.equal(false);
+
+    await completeAllCall(funded, 4, 4).rpc();
+    account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.takerCollected).to.equal(false);
+
+    await completeAllCall(funded, 8, 2).rpc();
+    account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.takerNftCollected.every((c) => c)).to.equal(true);
+    expect(account.takerCollected).to.equal(true);
+
+    for (const mint of funded.taker.mints) {
+      const ata = getAssociatedTokenAddressSync(
+        mint,
+        funded.initializer.keypair.publicKey
+      );
+      const balance = await provider.connection.getTokenAccountBalance(ata);
+      expect(balance.value.amount).to.equal("1");
+    }
+  });
+
+  it("rejects a slice running past the end of the side", async () => {
+    const funded = await setupFundedEscrow(1, 3);
+
+    await expectEscrowError(
+      completeAllCall(funded, 2, 2).rpc(),
+      "InvalidNftIndex"
+    );
+  });
+});
@@ -0,0 +1,72 @@
+import { expect } from "chai";
+import {
+  completeCall,
+  depositCall,
+  expectEscrowError,
+  finalizeCall,
+  program,
+  provider,
+  setupFundedEscrow,
+  vaultFor,
+} from "./utils";
+
+describe("closing an escrow", () => {
+  it("leaves collection to complete and closing to finalize", async () => {
+    const funded = await setupFundedEscrow(1, 1);
+
+    await expectEscrowError(finalizeCall(funded).rpc(), "InvalidEscrowStatus");
+
+    await completeCall(funded, true, 0).rpc();
+    await completeCall(funded, false, 0).rpc();
+
+    // Every NFT has moved, but the escrow stays open until it is finalized
+    const account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.status).to.deep.equal({ completed: {} });
+  });
+
+  it("leaves nothing a later instruction in the same transaction can load", async () => {
+    const funded = await setupFundedEscrow(1, 1);
+    await completeCall(funded, true, 0).rpc();
+    await completeCall(funded, false, 0).rpc();
+
+    // Finalize followed by a deposit into the now-closed escrow, atomically
+    const depositIx = await depositCall(
+      funded.escrow,
+      funded.taker,
+      false,
+      0
+    ).instruction();
+    await expectEscrowError(
+      finalizeCall(funded).postInstructions([depositIx]).rpc(),
+      "AccountNotInitialized"
+    );
+
+    // The whole transaction rolled back, so the escrow is still there to be finalized
+    expect(await provider.connection.getAccountInfo(funded.escrow)).to.not.equal(
+      null
+    );
+    await finalizeCall(funded).rpc();
+    expect(await provider.connection.getAccountInfo(funded.escrow)).to.equal(
+      null
+    );
+  });
+
+  it("reports the rent that closing the escrow and its vaults returns", async () => {
+    const funded = await setupFundedEscrow(2, 1);
+    const reclaimable = () =>
+      program.methods
+        .getReclaimableRent()
+        .accounts({ escrowAccount: funded.escrow })
+        .view();
+
+    const escrowRent = await provider.connection.getBalance(funded.escrow);
+    const vaultRent = await provider.connection.getBalance(
+      vaultFor(funded.escrow, funded.initializer.mints[0])
+    );
+    expect((await reclaimable()).toNumber()).to.equal(escrowRent + 3 * vaultRent);
+
+    // Each collection closes one vault
+    await completeCall(funded, true, 0).rpc();
+    expect((await reclaimable()).toNumber()).to.equal(escrowRent + 2 * vaultRent);
+  });
+});
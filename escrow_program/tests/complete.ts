@@ -0,0 +1,110 @@
+import { getAssociatedTokenAddressSync } from "@solana/spl-token";
+import { ComputeBudgetProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  completeCall,
+  errorName,
+  expectEscrowError,
+  program,
+  setupFundedEscrow,
+  vaultFor,
+} from "./utils";
+
+describe("complete", () => {
+  it("rejects a second collection of the same index", async () => {
+    const funded = await setupFundedEscrow(1, 2);
+
+    await completeCall(funded, true, 0).rpc();
+
+    // The vault is gone by now, but the collected flag is checked first
+    await expectEscrowError(
+      completeCall(funded, true, 0).rpc(),
+      "NftAlreadyCollected"
+    );
+  });
+
+  it("lets exactly one of two simultaneous submissions through", async () => {
+    const funded = await setupFundedEscrow(1, 2);
+
+    // Vary the compute limit so the two transactions get distinct signatures
+    const submit = (units: number) =>
+      completeCall(funded, true, 1)
+        .preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units })])
+        .rpc()
+        .then(() => undefined)
+        .catch((err) => err);
+    const errors = await Promise.all([submit(400_000), submit(400_001)]);
+
+    const rejected = errors.filter((err) => err !== undefined);
+    expect(rejected).to.have.length(1);
+    expect(errorName(rejected[0])).to.equal("NftAlreadyCollected");
+  });
+
+  it("rejects accounts belonging to a different index", async () => {
+    const funded = await setupFundedEscrow(1, 2);
+    const initializer = funded.initializer.keypair.publicKey;
+    const mint1 = funded.taker.mints[1];
+
+    // Index 1's vault, wired into a collection of index 0
+    await expectEscrowError(
+      completeCall(funded, true, 0)
+        .accounts({ vaultAccount: vaultFor(funded.escrow, mint1) })
+        .rpc(),
+      "InvalidVaultAccounts"
+    );
+
+    // Index 1's mint and recipient account alongside index 0's vault
+    await expectEscrowError(
+      completeCall(funded, true, 0)
+        .accounts({
+          mint: mint1,
+          recipientTokenAccount: getAssociatedTokenAddressSync(
+            mint1,
+            initializer
+          ),
+        })
+        .rpc(),
+      "InvalidNftMint"
+    );
+
+    // Index 0's mint and vault, but index 1's recipient account
+    await expectEscrowError(
+      completeCall(funded, true, 0)
+        .accounts({
+          recipientTokenAccount: getAssociatedTokenAddressSync(
+            mint1,
+            initializer
+          ),
+        })
+        .rpc(),
+      "NotAssociatedTokenAccount"
+    );
+
+    // Properly wired, the same collection goes through
+    await completeCall(funded, true, 0).rpc();
+    const account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.takerNftCollected).to.deep.equal([true, false]);
+  });
+
+  it("reports a single slot's state through check_nft_state", async () => {
+    const funded = await setupFundedEscrow(1, 2);
+    const nftState = (isInitializer: boolean, nftIndex: number) =>
+      program.methods
+        .checkNftState(isInitializer, nftIndex)
+        .accounts({ escrowAccount: funded.escrow })
+        .view();
+
+    await completeCall(funded, true, 0).rpc();
+
+    // Collecting as the initializer moves a taker NFT
+    expect(await nftState(false, 0)).to.deep.equal({
+      deposited: true,
+      collected: true,
+    });
+    expect(await nftState(false, 1)).to.deep.equal({
+      deposited: true,
+      collected: false,
+    });
+    await expectEscrowError(nftState(false, 2), "InvalidNftIndex");
+  });
+});
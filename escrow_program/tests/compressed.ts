@@ -0,0 +1,124 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import {
+  Party,
+  airdrop,
+  configPda,
+  createParty,
+  expectEscrowError,
+  initializeEscrow,
+  program,
+} from "./utils";
+
+const BUBBLEGUM_PROGRAM_ID = new PublicKey(
+  "BGUMAp9Gq7iTEuizy4pqaxsTyUCBK68MDfK752saRPUY"
+);
+const SPL_ACCOUNT_COMPRESSION_PROGRAM_ID = new PublicKey(
+  "cmtDvXumGCrqC1Age74AVPhSRVXJMd8PJS91L8KbNCK"
+);
+const SPL_NOOP_PROGRAM_ID = new PublicKey(
+  "noopb9bkMVfRPU8AsbpTUg8AQkHtKwMYZiFUjNRtMmV"
+);
+
+function assetId(merkleTree: PublicKey, nonce: number): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("asset"),
+      merkleTree.toBuffer(),
+      new anchor.BN(nonce).toArrayLike(Buffer, "le", 8),
+    ],
+    BUBBLEGUM_PROGRAM_ID
+  )[0];
+}
+
+/**
+ * Builds a `deposit_compressed` of the initializer's only slot. Both checks under test run
+ * before the Bubblegum CPI, so the tree and leaf never need to exist.
+ */
+function depositCompressedCall(
+  escrow: PublicKey,
+  initializer: Party,
+  depositor: Keypair,
+  merkleTree: PublicKey,
+  nonce: number,
+  leafDelegate: PublicKey
+) {
+  const zeros = new Array(32).fill(0);
+  return program.methods
+    .depositCompressed(true, 0, {
+      root: zeros,
+      dataHash: zeros,
+      creatorHash: zeros,
+      nonce: new anchor.BN(nonce),
+      index: nonce,
+    })
+    .accounts({
+      depositor: depositor.publicKey,
+      escrowAccount: escrow,
+      config: configPda,
+      leafOwner: initializer.keypair.publicKey,
+      leafDelegate,
+      treeConfig: PublicKey.findProgramAddressSync(
+        [merkleTree.toBuffer()],
+        BUBBLEGUM_PROGRAM_ID
+      )[0],
+      merkleTree,
+      logWrapper: SPL_NOOP_PROGRAM_ID,
+      compressionProgram: SPL_ACCOUNT_COMPRESSION_PROGRAM_ID,
+      bubblegumProgram: BUBBLEGUM_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    })
+    .signers([depositor]);
+}
+
+describe("compressed NFTs", () => {
+  const merkleTree = Keypair.generate().publicKey;
+
+  /** An escrow whose initializer slot lists the cNFT at nonce 0 in `merkleTree`. */
+  async function compressedEscrow(delegate?: Keypair) {
+    const initializer: Party = {
+      keypair: (await createParty(0)).keypair,
+      mints: [assetId(merkleTree, 0)],
+    };
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker, {
+      initializerDelegate: delegate?.publicKey,
+    });
+    return { escrow, initializer };
+  }
+
+  it("rejects a leaf whose asset id isn't the one listed", async () => {
+    const { escrow, initializer } = await compressedEscrow();
+
+    await expectEscrowError(
+      depositCompressedCall(
+        escrow,
+        initializer,
+        initializer.keypair,
+        merkleTree,
+        1,
+        initializer.keypair.publicKey
+      ).rpc(),
+      "InvalidNftMint"
+    );
+  });
+
+  it("rejects a delegate that isn't the leaf's owner or delegate", async () => {
+    const delegate = Keypair.generate();
+    await airdrop(delegate.publicKey, 1);
+    const { escrow, initializer } = await compressedEscrow(delegate);
+
+    // The escrow accepts the delegate, but the leaf is delegated to nobody but its owner
+    await expectEscrowError(
+      depositCompressedCall(
+        escrow,
+        initializer,
+        delegate,
+        merkleTree,
+        0,
+        initializer.keypair.publicKey
+      ).rpc(),
+      "InvalidDepositor"
+    );
+  });
+});
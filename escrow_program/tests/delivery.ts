@@ -0,0 +1,142 @@
+import * as anchor from "@coral-xyz/anchor";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  createAssociatedTokenAccount,
+  getAccount,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
+import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  Party,
+  completeCall,
+  configPda,
+  confirmReady,
+  createParty,
+  deposit,
+  expectEscrowError,
+  initializeEscrow,
+  program,
+  provider,
+  vaultFor,
+} from "./utils";
+
+function setDeliveryAddressCall(
+  escrow: PublicKey,
+  party: Party,
+  deliveryAddress: PublicKey | null
+) {
+  return program.methods
+    .setDeliveryAddress(deliveryAddress)
+    .accounts({ party: party.keypair.publicKey, escrowAccount: escrow })
+    .signers([party.keypair]);
+}
+
+describe("delivery address", () => {
+  it("delivers the counterpart's NFT to the address set before deposits", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const coldWallet = Keypair.generate().publicKey;
+    const escrow = await initializeEscrow(initializer, taker);
+
+    await setDeliveryAddressCall(escrow, initializer, coldWallet).rpc();
+
+    await deposit(escrow, initializer, true, 0);
+    await deposit(escrow, taker, false, 0);
+
+    // Once anything is deposited the address is fixed
+    await expectEscrowError(
+      setDeliveryAddressCall(escrow, initializer, null).rpc(),
+      "DeliveryAddressLocked"
+    );
+
+    await confirmReady(escrow, initializer);
+    await confirmReady(escrow, taker);
+
+    const funded = { escrow, initializer, taker };
+    const mint = taker.mints[0];
+    const coldAta = getAssociatedTokenAddressSync(mint, coldWallet);
+
+    // The initializer's own ATA is refused, and so is a missing delivery wallet
+    await expectEscrowError(completeCall(funded, true, 0).rpc(), "InvalidRecipient");
+
+    await completeCall(funded, true, 0)
+      .accounts({ recipientTokenAccount: coldAta, deliveryWallet: coldWallet })
+      .rpc();
+
+    const delivered = await getAccount(provider.connection, coldAta);
+    expect(Number(delivered.amount)).to.equal(1);
+  });
+
+  it("delivers a complete_all slice to the address and reports it", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(2);
+    const coldWallet = Keypair.generate().publicKey;
+    const escrow = await initializeEscrow(initializer, taker);
+
+    await setDeliveryAddressCall(escrow, initializer, coldWallet).rpc();
+    await deposit(escrow, initializer, true, 0);
+    await deposit(escrow, taker, false, 0);
+    await deposit(escrow, taker, false, 1);
+    await confirmReady(escrow, initializer);
+    await confirmReady(escrow, taker);
+
+    // ATAs at a delivery address are not created by the collection itself
+    const coldAtas: PublicKey[] = [];
+    for (const mint of taker.mints) {
+      coldAtas.push(
+        await createAssociatedTokenAccount(
+          provider.connection,
+          initializer.keypair,
+          mint,
+          coldWallet
+        )
+      );
+    }
+
+    const signature = await program.methods
+      .completeAll(true, 0, 2)
+      .accounts({
+        caller: initializer.keypair.publicKey,
+        escrowAccount: escrow,
+        config: configPda,
+        recipient: initializer.keypair.publicKey,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .remainingAccounts(
+        taker.mints.flatMap((mint, i) => [
+          { pubkey: mint, isSigner: false, isWritable: false },
+          { pubkey: vaultFor(escrow, mint), isSigner: false, isWritable: true },
+          { pubkey: coldAtas[i], isSigner: false, isWritable: true },
+          {
+            pubkey: taker.keypair.publicKey,
+            isSigner: false,
+            isWritable: true,
+          },
+        ])
+      )
+      .signers([initializer.keypair])
+      .rpc({ commitment: "confirmed" });
+
+    for (const ata of coldAtas) {
+      const delivered = await getAccount(provider.connection, ata);
+      expect(Number(delivered.amount)).to.equal(1);
+    }
+
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, program.coder);
+    const recipients = [...parser.parseLogs(tx.meta.logMessages)]
+      .filter((event) => event.name === "NftCollected")
+      .map((event) => event.data.recipient.toBase58());
+    expect(recipients).to.deep.equal([
+      coldWallet.toBase58(),
+      coldWallet.toBase58(),
+    ]);
+  });
+});
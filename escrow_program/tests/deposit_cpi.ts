@@ -0,0 +1,82 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  createMint,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import { Keypair, PublicKey, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import { EscrowCpiTester } from "../target/types/escrow_cpi_tester";
+import {
+  Party,
+  configPda,
+  createParty,
+  initializeEscrow,
+  program,
+  provider,
+  vaultFor,
+} from "./utils";
+
+const tester = anchor.workspace.EscrowCpiTester as Program<EscrowCpiTester>;
+
+const [testerDepositor] = PublicKey.findProgramAddressSync(
+  [Buffer.from("depositor")],
+  tester.programId
+);
+
+describe("deposit via CPI", () => {
+  it("accepts a deposit signed by another program's PDA", async () => {
+    const initializer = await createParty(1);
+    const payer = (provider.wallet as anchor.Wallet).payer;
+
+    // The taker is the tester's PDA, holding one NFT in its associated token account
+    const mint = await createMint(
+      provider.connection,
+      payer,
+      payer.publicKey,
+      null,
+      0
+    );
+    const tokenAccount = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      payer,
+      mint,
+      testerDepositor,
+      true
+    );
+    await mintTo(provider.connection, payer, mint, tokenAccount.address, payer, 1);
+    const taker: Party = {
+      keypair: { publicKey: testerDepositor } as Keypair,
+      mints: [mint],
+    };
+
+    const escrow = await initializeEscrow(initializer, taker);
+
+    await tester.methods
+      .deposit(false, 0)
+      .accounts({
+        payer: payer.publicKey,
+        depositor: testerDepositor,
+        escrowAccount: escrow,
+        config: configPda,
+        mint,
+        tokenAccount: getAssociatedTokenAddressSync(mint, testerDepositor, true),
+        vaultAccount: vaultFor(escrow, mint),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        swapEscrowProgram: program.programId,
+      })
+      .rpc();
+
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.takerNftDeposited[0]).to.equal(true);
+    const vault = await getAccount(provider.connection, vaultFor(escrow, mint));
+    expect(Number(vault.amount)).to.equal(1);
+  });
+});
@@ -0,0 +1,56 @@
+import { SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  Party,
+  createParty,
+  escrowListPda,
+  expectEscrowError,
+  initializeEscrow,
+  program,
+} from "./utils";
+
+async function createEscrowList(owner: Party) {
+  await program.methods
+    .createEscrowList()
+    .accounts({
+      owner: owner.keypair.publicKey,
+      escrowList: escrowListPda(owner.keypair.publicKey),
+      systemProgram: SystemProgram.programId,
+    })
+    .signers([owner.keypair])
+    .rpc();
+}
+
+describe("escrow lists", () => {
+  it("records an escrow in its initializer's list", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    await createEscrowList(initializer);
+    const list = escrowListPda(initializer.keypair.publicKey);
+
+    const escrow = await initializeEscrow(initializer, taker, {
+      lists: { initializer: list },
+    });
+
+    const listed = await program.account.userEscrowList.fetch(list);
+    expect(listed.escrows.map((key) => key.toBase58())).to.deep.equal([
+      escrow.toBase58(),
+    ]);
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.listedInitializer).to.be.true;
+    expect(account.listedTaker).to.be.false;
+  });
+
+  it("won't write to a taker's list without the taker's signature", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    await createEscrowList(taker);
+
+    await expectEscrowError(
+      initializeEscrow(initializer, taker, {
+        lists: { taker: escrowListPda(taker.keypair.publicKey) },
+      }),
+      "EscrowListOwnerMustSign"
+    );
+  });
+});
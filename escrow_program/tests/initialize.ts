@@ -0,0 +1,24 @@
+import { createParty, expectEscrowError, initializeEscrow } from "./utils";
+
+describe("initialize", () => {
+  it("rejects an escrow where neither side provides NFTs", async () => {
+    const initializer = await createParty(0);
+    const taker = await createParty(0);
+
+    await expectEscrowError(
+      initializeEscrow(initializer, taker),
+      "EmptyBothSides"
+    );
+  });
+
+  it("rejects a taker side with no NFTs and nothing else to pay", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(0);
+
+    // initializeEscrow sets up no SOL or token leg
+    await expectEscrowError(
+      initializeEscrow(initializer, taker),
+      "InvalidNftCount"
+    );
+  });
+});
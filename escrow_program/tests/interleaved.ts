@@ -0,0 +1,55 @@
+import { expect } from "chai";
+import {
+  completeCall,
+  expectEscrowError,
+  finalizeCall,
+  program,
+  provider,
+  setupFundedEscrow,
+  vaultFor,
+} from "./utils";
+
+describe("interleaved collection", () => {
+  it("completes once, on the last collection, whatever the order", async () => {
+    const funded = await setupFundedEscrow(3, 3);
+    const initializer = funded.initializer.keypair.publicKey;
+
+    // (isInitializer, nftIndex): the initializer collects taker NFTs and vice versa
+    const order: [boolean, number][] = [
+      [false, 2],
+      [true, 0],
+      [false, 0],
+      [true, 2],
+      [true, 1],
+    ];
+    for (const [isInitializer, nftIndex] of order) {
+      await completeCall(funded, isInitializer, nftIndex).rpc();
+      const account = await program.account.escrowAccount.fetch(funded.escrow);
+      expect(account.status).to.deep.equal({ completing: {} });
+      await expectEscrowError(finalizeCall(funded).rpc(), "InvalidEscrowStatus");
+    }
+
+    // The taker's last collection returns the last vault's rent to the initializer, who
+    // funded it, and leaves the escrow itself to finalize
+    const vaultRent = await provider.connection.getBalance(
+      vaultFor(funded.escrow, funded.initializer.mints[1])
+    );
+    let before = await provider.connection.getBalance(initializer);
+    await completeCall(funded, false, 1).rpc();
+    let after = await provider.connection.getBalance(initializer);
+    expect(after - before).to.equal(vaultRent);
+    const account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.status).to.deep.equal({ completed: {} });
+
+    // Finalizing returns the escrow's rent exactly once
+    const escrowRent = await provider.connection.getBalance(funded.escrow);
+    before = await provider.connection.getBalance(initializer);
+    await finalizeCall(funded).rpc();
+    after = await provider.connection.getBalance(initializer);
+    expect(after - before).to.equal(escrowRent);
+    expect(await program.account.escrowAccount.fetchNullable(funded.escrow)).to
+      .be.null;
+
+    await expectEscrowError(finalizeCall(funded).rpc(), "AccountNotInitialized");
+  });
+});
@@ -0,0 +1,178 @@
+import * as anchor from "@coral-xyz/anchor";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
+import { PublicKey, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  completeCall,
+  configPda,
+  createParty,
+  deposit,
+  expectEscrowError,
+  finalizeCall,
+  initializeEscrow,
+  initializerStatsPda,
+  program,
+  provider,
+  setupFundedEscrow,
+  vaultFor,
+} from "./utils";
+
+async function setPaused(paused: boolean): Promise<void> {
+  await program.methods
+    .setPaused(paused)
+    .accounts({ authority: provider.wallet.publicKey, config: configPda })
+    .rpc();
+}
+
+/** Runs `body` with the program paused, unpausing again whatever happens. */
+async function whilePaused(body: () => Promise<void>): Promise<void> {
+  await setPaused(true);
+  try {
+    await body();
+  } finally {
+    await setPaused(false);
+  }
+}
+
+describe("pause", () => {
+  it("holds completions until the program is unpaused", async () => {
+    const funded = await setupFundedEscrow(1, 1);
+
+    await whilePaused(() =>
+      expectEscrowError(completeCall(funded, true, 0).rpc(), "ProgramPaused")
+    );
+
+    await completeCall(funded, true, 0).rpc();
+    const account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.takerNftCollected).to.deep.equal([true]);
+  });
+
+  it("holds finalize until the program is unpaused", async () => {
+    const funded = await setupFundedEscrow(1, 1);
+    await completeCall(funded, true, 0).rpc();
+    await completeCall(funded, false, 0).rpc();
+
+    await whilePaused(() =>
+      expectEscrowError(finalizeCall(funded).rpc(), "ProgramPaused")
+    );
+
+    await finalizeCall(funded).rpc();
+    expect(await program.account.escrowAccount.fetchNullable(funded.escrow)).to
+      .be.null;
+  });
+
+  it("holds an arbiter's completion while paused", async () => {
+    const arbiter = provider.wallet.publicKey;
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker, { arbiter });
+    await deposit(escrow, initializer, true, 0);
+    await deposit(escrow, taker, false, 0);
+
+    const settle = program.methods
+      .arbiterSettle({ complete: {} })
+      .accounts({
+        arbiter,
+        escrowAccount: escrow,
+        initializer: initializer.keypair.publicKey,
+        taker: taker.keypair.publicKey,
+        rentPayer: initializer.keypair.publicKey,
+        initializerStats: initializerStatsPda(initializer.keypair.publicKey),
+        initializerEscrowList: null,
+        takerEscrowList: null,
+        feeRecipient: initializer.keypair.publicKey,
+        tokenLegMint: null,
+        tokenLegVault: null,
+        tokenLegDestination: null,
+        tokenLegVaultFunder: null,
+        config: configPda,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      });
+
+    await whilePaused(() =>
+      expectEscrowError(settle.rpc(), "ProgramPaused")
+    );
+
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.inProgress).to.be.false;
+  });
+
+  it("still lets a depositor withdraw while paused", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const escrow = await initializeEscrow(initializer, taker);
+    await deposit(escrow, initializer, true, 0);
+    const mint = initializer.mints[0];
+
+    await whilePaused(async () => {
+      await program.methods
+        .withdrawDeposited(true, 0)
+        .accounts({
+          depositor: initializer.keypair.publicKey,
+          escrowAccount: escrow,
+          vaultFunder: initializer.keypair.publicKey,
+          mint,
+          vaultAccount: vaultFor(escrow, mint),
+          depositorTokenAccount: getAssociatedTokenAddressSync(
+            mint,
+            initializer.keypair.publicKey
+          ),
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([initializer.keypair])
+        .rpc();
+    });
+
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.initializerNftDeposited).to.deep.equal([false]);
+  });
+
+  it("pauses itself once enough completion failures are reported", async () => {
+    const admin = provider.wallet.publicKey;
+    const setBreaker = (threshold: number) =>
+      program.methods
+        .setCircuitBreaker(admin, threshold, new anchor.BN(3600))
+        .accounts({ admin, config: configPda })
+        .rpc();
+    const report = () =>
+      program.methods
+        .reportCompletionFailure(PublicKey.default)
+        .accounts({ reporter: admin, config: configPda })
+        .rpc();
+
+    await setBreaker(2);
+    try {
+      await report();
+      expect((await program.account.config.fetch(configPda)).paused).to.be
+        .false;
+
+      await report();
+      const config = await program.account.config.fetch(configPda);
+      expect(config.paused).to.be.true;
+      expect(config.failureCount).to.equal(2);
+    } finally {
+      await setPaused(false);
+      await setBreaker(0);
+    }
+  });
+
+  it("rejects a failure threshold without a window", async () => {
+    const admin = provider.wallet.publicKey;
+
+    await expectEscrowError(
+      program.methods
+        .setCircuitBreaker(admin, 2, new anchor.BN(0))
+        .accounts({ admin, config: configPda })
+        .rpc(),
+      "InvalidFailureWindow"
+    );
+  });
+});
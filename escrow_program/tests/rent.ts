@@ -0,0 +1,95 @@
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  getAssociatedTokenAddressSync,
+} from "@solana/spl-token";
+import { PublicKey, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  completeCall,
+  confirmReady,
+  createParty,
+  deposit,
+  finalizeCall,
+  initializeEscrow,
+  initializerStatsPda,
+  program,
+  provider,
+  vaultFor,
+} from "./utils";
+
+// Transaction fees are paid by the provider wallet, so a party's balance only moves with rent
+const balance = (pubkey: PublicKey) => provider.connection.getBalance(pubkey);
+
+describe("rent bookkeeping", () => {
+  it("returns every party's rent across a full swap", async () => {
+    const initializer = await createParty(2);
+    const taker = await createParty(2);
+    const initializerKey = initializer.keypair.publicKey;
+    const takerKey = taker.keypair.publicKey;
+    const initializerBefore = await balance(initializerKey);
+    const takerBefore = await balance(takerKey);
+
+    const escrow = await initializeEscrow(initializer, taker);
+    for (let i = 0; i < 2; i++) {
+      await deposit(escrow, initializer, true, i);
+      await deposit(escrow, taker, false, i);
+    }
+    await confirmReady(escrow, initializer);
+    await confirmReady(escrow, taker);
+
+    const funded = { escrow, initializer, taker };
+    for (let i = 0; i < 2; i++) {
+      await completeCall(funded, true, i).rpc();
+      await completeCall(funded, false, i).rpc();
+    }
+    await finalizeCall(funded).rpc();
+
+    // Each party is left paying only for accounts it still owns: the token accounts now
+    // holding the NFTs it received, plus the initializer's active escrow counter
+    const ataRent = await balance(
+      getAssociatedTokenAddressSync(taker.mints[0], initializerKey)
+    );
+    const statsRent = await balance(initializerStatsPda(initializerKey));
+    expect(initializerBefore - (await balance(initializerKey))).to.equal(
+      2 * ataRent + statsRent
+    );
+    expect(takerBefore - (await balance(takerKey))).to.equal(2 * ataRent);
+  });
+
+  it("refunds a withdrawn NFT's vault to whoever funded it", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const initializerKey = initializer.keypair.publicKey;
+    const escrow = await initializeEscrow(initializer, taker);
+    const mint = initializer.mints[0];
+    const vault = vaultFor(escrow, mint);
+
+    const before = await balance(initializerKey);
+    await deposit(escrow, initializer, true, 0);
+    await program.methods
+      .withdrawDeposited(true, 0)
+      .accounts({
+        depositor: initializerKey,
+        escrowAccount: escrow,
+        vaultFunder: initializerKey,
+        mint,
+        vaultAccount: vault,
+        depositorTokenAccount: getAssociatedTokenAddressSync(
+          mint,
+          initializerKey
+        ),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([initializer.keypair])
+      .rpc();
+
+    expect(await provider.connection.getAccountInfo(vault)).to.equal(null);
+    expect(await balance(initializerKey)).to.equal(before);
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.initializerVaultFunders[0].equals(PublicKey.default)).to.be
+      .true;
+  });
+});
@@ -0,0 +1,62 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Keypair, LAMPORTS_PER_SOL, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import {
+  completeCall,
+  configPda,
+  confirmReady,
+  createMetadata,
+  createParty,
+  deposit,
+  initializeEscrow,
+  program,
+  provider,
+} from "./utils";
+
+describe("royalties", () => {
+  it("pays the initializer NFT's creators out of the SOL on complete", async () => {
+    const initializer = await createParty(1);
+    const taker = await createParty(1);
+    const creators = [Keypair.generate(), Keypair.generate()].map(
+      (keypair) => keypair.publicKey
+    );
+    const metadata = await createMetadata(initializer, 0, 500, [
+      { address: creators[0], share: 60 },
+      { address: creators[1], share: 40 },
+    ]);
+
+    const escrow = await initializeEscrow(initializer, taker, {
+      solAmount: LAMPORTS_PER_SOL,
+      enforceRoyalties: true,
+    });
+    await deposit(escrow, initializer, true, 0);
+    await deposit(escrow, taker, false, 0);
+    await program.methods
+      .depositSol()
+      .accounts({
+        taker: taker.keypair.publicKey,
+        escrowAccount: escrow,
+        config: configPda,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([taker.keypair])
+      .rpc();
+    await confirmReady(escrow, initializer);
+    await confirmReady(escrow, taker);
+
+    await completeCall({ escrow, initializer, taker }, false, 0)
+      .accounts({ metadataAccount: metadata })
+      .remainingAccounts(
+        creators.map((pubkey) => ({ pubkey, isSigner: false, isWritable: true }))
+      )
+      .rpc();
+
+    // 5% of the one NFT's full share of the SOL, split 60/40
+    const balances = await Promise.all(
+      creators.map((creator) => provider.connection.getBalance(creator))
+    );
+    expect(balances).to.deep.equal([30_000_000, 20_000_000]);
+    const account = await program.account.escrowAccount.fetch(escrow);
+    expect(account.royaltiesPaid.eq(new anchor.BN(50_000_000))).to.be.true;
+  });
+});
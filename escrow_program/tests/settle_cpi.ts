@@ -0,0 +1,121 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getOrCreateAssociatedTokenAccount,
+} from "@solana/spl-token";
+import { PublicKey, SystemProgram } from "@solana/web3.js";
+import { expect } from "chai";
+import { EscrowCpiTester } from "../target/types/escrow_cpi_tester";
+import {
+  configPda,
+  initializerStatsPda,
+  program,
+  provider,
+  setupFundedEscrow,
+  vaultFor,
+} from "./utils";
+
+const tester = anchor.workspace.EscrowCpiTester as Program<EscrowCpiTester>;
+
+const [testerSettler] = PublicKey.findProgramAddressSync(
+  [Buffer.from("settler")],
+  tester.programId
+);
+
+describe("complete and settle via CPI", () => {
+  it("completes through another program with the party's signature", async () => {
+    const funded = await setupFundedEscrow(1, 2);
+    const initializer = funded.initializer.keypair;
+    const mint = funded.taker.mints[0];
+
+    await tester.methods
+      .complete(true, 0)
+      .accounts({
+        caller: initializer.publicKey,
+        escrowAccount: funded.escrow,
+        config: configPda,
+        vaultFunder: funded.taker.keypair.publicKey,
+        mint,
+        vaultAccount: vaultFor(funded.escrow, mint),
+        recipientTokenAccount: getAssociatedTokenAddressSync(
+          mint,
+          initializer.publicKey
+        ),
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        swapEscrowProgram: program.programId,
+      })
+      .signers([initializer])
+      .rpc();
+
+    const account = await program.account.escrowAccount.fetch(funded.escrow);
+    expect(account.takerNftCollected).to.deep.equal([true, false]);
+  });
+
+  it("settles with another program's PDA as the caller", async () => {
+    const funded = await setupFundedEscrow(1, 1);
+    const payer = (provider.wallet as anchor.Wallet).payer;
+    const initializer = funded.initializer.keypair.publicKey;
+    const taker = funded.taker.keypair.publicKey;
+    const takerMint = funded.taker.mints[0];
+    const initializerMint = funded.initializer.mints[0];
+
+    // settle never creates ATAs, so the recipients' accounts exist up front
+    const initializerAta = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      payer,
+      takerMint,
+      initializer
+    );
+    const takerAta = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      payer,
+      initializerMint,
+      taker
+    );
+
+    const meta = (pubkey: PublicKey, isWritable: boolean) => ({
+      pubkey,
+      isSigner: false,
+      isWritable,
+    });
+
+    await tester.methods
+      .settle()
+      .accounts({
+        settler: testerSettler,
+        escrowAccount: funded.escrow,
+        config: configPda,
+        initializer,
+        taker,
+        rentPayer: initializer,
+        initializerStats: initializerStatsPda(initializer),
+        feeRecipient: initializer,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        swapEscrowProgram: program.programId,
+      })
+      .remainingAccounts([
+        meta(takerMint, false),
+        meta(vaultFor(funded.escrow, takerMint), true),
+        meta(initializerAta.address, true),
+        meta(taker, true),
+        meta(initializerMint, false),
+        meta(vaultFor(funded.escrow, initializerMint), true),
+        meta(takerAta.address, true),
+        meta(initializer, true),
+      ])
+      .rpc();
+
+    expect(await program.account.escrowAccount.fetchNullable(funded.escrow)).to
+      .be.null;
+    const received = await getAccount(provider.connection, initializerAta.address);
+    expect(Number(received.amount)).to.equal(1);
+  });
+});
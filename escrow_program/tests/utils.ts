@@ -0,0 +1,454 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import {
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  TOKEN_PROGRAM_ID,
+  createMint,
+  getAssociatedTokenAddressSync,
+  getOrCreateAssociatedTokenAccount,
+  mintTo,
+} from "@solana/spl-token";
+import {
+  Keypair,
+  LAMPORTS_PER_SOL,
+  PublicKey,
+  SystemProgram,
+  SYSVAR_RENT_PUBKEY,
+  Transaction,
+  TransactionInstruction,
+} from "@solana/web3.js";
+import { expect } from "chai";
+import { SwapEscrow } from "../target/types/swap_escrow";
+
+export const provider = anchor.AnchorProvider.env();
+anchor.setProvider(provider);
+
+export const program = anchor.workspace.SwapEscrow as Program<SwapEscrow>;
+
+export const [configPda] = PublicKey.findProgramAddressSync(
+  [Buffer.from("config")],
+  program.programId
+);
+
+export interface Party {
+  keypair: Keypair;
+  mints: PublicKey[];
+}
+
+export interface FundedEscrow {
+  escrow: PublicKey;
+  initializer: Party;
+  taker: Party;
+}
+
+export async function airdrop(to: PublicKey, sol = 10): Promise<void> {
+  const signature = await provider.connection.requestAirdrop(
+    to,
+    sol * LAMPORTS_PER_SOL
+  );
+  const latest = await provider.connection.getLatestBlockhash();
+  await provider.connection.confirmTransaction({ signature, ...latest });
+}
+
+export async function ensureConfig(): Promise<void> {
+  const existing = await program.account.config.fetchNullable(configPda);
+  if (existing) {
+    return;
+  }
+  await program.methods
+    .initializeConfig()
+    .accounts({
+      admin: provider.wallet.publicKey,
+      config: configPda,
+      systemProgram: SystemProgram.programId,
+    })
+    .rpc();
+}
+
+/** Creates a funded party holding `count` freshly minted NFTs. */
+export async function createParty(count: number): Promise<Party> {
+  const keypair = Keypair.generate();
+  await airdrop(keypair.publicKey);
+
+  const mints: PublicKey[] = [];
+  for (let i = 0; i < count; i++) {
+    const mint = await createMint(
+      provider.connection,
+      keypair,
+      keypair.publicKey,
+      null,
+      0
+    );
+    const ata = await getOrCreateAssociatedTokenAccount(
+      provider.connection,
+      keypair,
+      mint,
+      keypair.publicKey
+    );
+    await mintTo(provider.connection, keypair, mint, ata.address, keypair, 1);
+    mints.push(mint);
+  }
+  return { keypair, mints };
+}
+
+export function escrowPda(
+  initializer: PublicKey,
+  taker: PublicKey,
+  nonce: anchor.BN
+): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("escrow"),
+      initializer.toBuffer(),
+      taker.toBuffer(),
+      nonce.toArrayLike(Buffer, "le", 8),
+    ],
+    program.programId
+  )[0];
+}
+
+export function initializerStatsPda(initializer: PublicKey): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("initializer_stats"), initializer.toBuffer()],
+    program.programId
+  )[0];
+}
+
+export function escrowListPda(owner: PublicKey): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("escrow_list"), owner.toBuffer()],
+    program.programId
+  )[0];
+}
+
+export const TOKEN_METADATA_PROGRAM_ID = new PublicKey(
+  "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bWg8HTp"
+);
+
+export function metadataPda(mint: PublicKey): PublicKey {
+  return PublicKey.findProgramAddressSync(
+    [
+      Buffer.from("metadata"),
+      TOKEN_METADATA_PROGRAM_ID.toBuffer(),
+      mint.toBuffer(),
+    ],
+    TOKEN_METADATA_PROGRAM_ID
+  )[0];
+}
+
+function borshString(value: string): Buffer {
+  const bytes = Buffer.from(value);
+  const len = Buffer.alloc(4);
+  len.writeUInt32LE(bytes.length);
+  return Buffer.concat([len, bytes]);
+}
+
+/**
+ * Creates Metaplex metadata for the NFT at `nftIndex` of `party`, who holds its mint
+ * authority. `creators` get unverified entries with the given percentage shares.
+ */
+export async function createMetadata(
+  party: Party,
+  nftIndex: number,
+  sellerFeeBasisPoints: number,
+  creators: { address: PublicKey; share: number }[]
+): Promise<PublicKey> {
+  const mint = party.mints[nftIndex];
+  const metadata = metadataPda(mint);
+  const authority = party.keypair.publicKey;
+
+  const fee = Buffer.alloc(2);
+  fee.writeUInt16LE(sellerFeeBasisPoints);
+  const count = Buffer.alloc(4);
+  count.writeUInt32LE(creators.length);
+  // CreateMetadataAccountV3: DataV2, is_mutable and no collection details
+  const data = Buffer.concat([
+    Buffer.from([33]),
+    borshString("Escrow test"),
+    borshString("ESC"),
+    borshString(""),
+    fee,
+    Buffer.from([1]),
+    count,
+    ...creators.map(({ address, share }) =>
+      Buffer.concat([address.toBuffer(), Buffer.from([0, share])])
+    ),
+    Buffer.from([0, 0, 1, 0]),
+  ]);
+
+  const instruction = new TransactionInstruction({
+    programId: TOKEN_METADATA_PROGRAM_ID,
+    keys: [
+      { pubkey: metadata, isSigner: false, isWritable: true },
+      { pubkey: mint, isSigner: false, isWritable: false },
+      { pubkey: authority, isSigner: true, isWritable: false },
+      { pubkey: authority, isSigner: true, isWritable: true },
+      { pubkey: authority, isSigner: true, isWritable: false },
+      { pubkey: SystemProgram.programId, isSigner: false, isWritable: false },
+    ],
+    data,
+  });
+  await provider.sendAndConfirm(new Transaction().add(instruction), [
+    party.keypair,
+  ]);
+
+  return metadata;
+}
+
+export function vaultFor(escrow: PublicKey, mint: PublicKey): PublicKey {
+  return getAssociatedTokenAddressSync(mint, escrow, true);
+}
+
+export interface InitializeOptions {
+  nonce?: anchor.BN;
+  /** Deposits wait until the approver signs off on the escrow. */
+  approver?: PublicKey;
+  /** How many tokens each slot holds, initializer's first; empty means one NFT per slot. */
+  nftAmounts?: number[];
+  /** Can settle the escrow either way. */
+  arbiter?: PublicKey;
+  /** May deposit on the initializer's behalf. */
+  initializerDelegate?: PublicKey;
+  /** The escrow lists to record the escrow in. */
+  lists?: { initializer?: PublicKey; taker?: PublicKey };
+  /** Lamports the taker pays alongside their NFTs. */
+  solAmount?: number;
+  /** Pays the initializer NFTs' creators out of `solAmount`. */
+  enforceRoyalties?: boolean;
+}
+
+/**
+ * Initializes an NFT-for-NFT escrow with no token leg or fees, and no SOL payment unless
+ * `options` asks for one.
+ */
+export async function initializeEscrow(
+  initializer: Party,
+  taker: Party,
+  options: InitializeOptions = {}
+): Promise<PublicKey> {
+  const nonce = options.nonce ?? new anchor.BN(Date.now());
+  const lists = options.lists ?? {};
+  await ensureConfig();
+  const escrow = escrowPda(
+    initializer.keypair.publicKey,
+    taker.keypair.publicKey,
+    nonce
+  );
+
+  await program.methods
+    .initialize(
+      nonce,
+      initializer.mints.length,
+      taker.mints.length,
+      new anchor.BN(0),
+      new anchor.BN(options.solAmount ?? 0),
+      0,
+      0,
+      initializer.keypair.publicKey,
+      null,
+      null,
+      options.initializerDelegate ?? null,
+      null,
+      [],
+      PublicKey.default,
+      new anchor.BN(0),
+      null,
+      null,
+      new anchor.BN(0),
+      options.arbiter ?? null,
+      new anchor.BN(0),
+      options.enforceRoyalties ?? false,
+      false,
+      false,
+      new anchor.BN(0),
+      new Array(32).fill(0),
+      options.approver ?? null,
+      (options.nftAmounts ?? []).map((amount) => new anchor.BN(amount)),
+      null,
+      null
+    )
+    .accounts({
+      initializer: initializer.keypair.publicKey,
+      taker: taker.keypair.publicKey,
+      payer: initializer.keypair.publicKey,
+      escrowAccount: escrow,
+      initializerStats: initializerStatsPda(initializer.keypair.publicKey),
+      initializerEscrowList: lists.initializer ?? null,
+      takerEscrowList: lists.taker ?? null,
+      config: configPda,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      rent: SYSVAR_RENT_PUBKEY,
+    })
+    .remainingAccounts(
+      [...initializer.mints, ...taker.mints].map((pubkey) => ({
+        pubkey,
+        isSigner: false,
+        isWritable: false,
+      }))
+    )
+    .signers([initializer.keypair])
+    .rpc();
+
+  return escrow;
+}
+
+/** Builds a `deposit` call for the NFT at `nftIndex` of `party`. */
+export function depositCall(
+  escrow: PublicKey,
+  party: Party,
+  isInitializer: boolean,
+  nftIndex: number
+) {
+  const mint = party.mints[nftIndex];
+  return program.methods
+    .deposit(isInitializer, nftIndex)
+    .accounts({
+      depositor: party.keypair.publicKey,
+      payer: party.keypair.publicKey,
+      escrowAccount: escrow,
+      config: configPda,
+      mint,
+      metadataAccount: null,
+      tokenAccount: getAssociatedTokenAddressSync(
+        mint,
+        party.keypair.publicKey
+      ),
+      vaultAccount: vaultFor(escrow, mint),
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    })
+    .signers([party.keypair]);
+}
+
+export async function deposit(
+  escrow: PublicKey,
+  party: Party,
+  isInitializer: boolean,
+  nftIndex: number
+): Promise<void> {
+  await depositCall(escrow, party, isInitializer, nftIndex).rpc();
+}
+
+export async function confirmReady(
+  escrow: PublicKey,
+  party: Party
+): Promise<void> {
+  await program.methods
+    .confirmReady()
+    .accounts({ caller: party.keypair.publicKey, escrowAccount: escrow })
+    .signers([party.keypair])
+    .rpc();
+}
+
+/**
+ * Sets up an escrow where both sides have deposited every NFT and confirmed, ready for
+ * collection.
+ */
+export async function setupFundedEscrow(
+  initializerNfts = 1,
+  takerNfts = 1
+): Promise<FundedEscrow> {
+  const initializer = await createParty(initializerNfts);
+  const taker = await createParty(takerNfts);
+  const escrow = await initializeEscrow(initializer, taker);
+
+  for (let i = 0; i < initializer.mints.length; i++) {
+    await deposit(escrow, initializer, true, i);
+  }
+  for (let i = 0; i < taker.mints.length; i++) {
+    await deposit(escrow, taker, false, i);
+  }
+  await confirmReady(escrow, initializer);
+  await confirmReady(escrow, taker);
+
+  return { escrow, initializer, taker };
+}
+
+/**
+ * Builds a `complete` call for the NFT at `nftIndex` on the side coming to `collector`.
+ * `isInitializer` is true when the initializer collects one of the taker's NFTs.
+ */
+export function completeCall(
+  funded: FundedEscrow,
+  isInitializer: boolean,
+  nftIndex: number
+) {
+  const collector = isInitializer ? funded.initializer : funded.taker;
+  const depositor = isInitializer ? funded.taker : funded.initializer;
+  const mint = depositor.mints[nftIndex];
+
+  return program.methods
+    .complete(isInitializer, nftIndex)
+    .accounts({
+      caller: collector.keypair.publicKey,
+      escrowAccount: funded.escrow,
+      config: configPda,
+      vaultFunder: depositor.keypair.publicKey,
+      recipient: collector.keypair.publicKey,
+      mint,
+      metadataAccount: null,
+      vaultAccount: vaultFor(funded.escrow, mint),
+      recipientTokenAccount: getAssociatedTokenAddressSync(
+        mint,
+        collector.keypair.publicKey
+      ),
+      deliveryWallet: null,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+    })
+    .signers([collector.keypair]);
+}
+
+/** Builds a `finalize` call for an escrow whose NFTs have all been collected. */
+export function finalizeCall(funded: FundedEscrow) {
+  const initializer = funded.initializer.keypair.publicKey;
+
+  return program.methods.finalize().accounts({
+    caller: provider.wallet.publicKey,
+    escrowAccount: funded.escrow,
+    initializer,
+    rentPayer: initializer,
+    initializerStats: initializerStatsPda(initializer),
+    initializerEscrowList: null,
+    takerEscrowList: null,
+    feeRecipient: initializer,
+    tokenLegMint: null,
+    tokenLegVault: null,
+    tokenLegDestination: null,
+    tokenLegVaultFunder: null,
+    config: configPda,
+    tokenProgram: TOKEN_PROGRAM_ID,
+  });
+}
+
+/**
+ * Asserts that `promise` fails with the named program error, whether it was rejected in
+ * preflight (an AnchorError with logs) or only once the transaction landed.
+ */
+export async function expectEscrowError(
+  promise: Promise<unknown>,
+  name: string
+): Promise<void> {
+  try {
+    await promise;
+  } catch (err) {
+    expect(errorName(err)).to.equal(name);
+    return;
+  }
+  expect.fail(`expected ${name}, but the transaction succeeded`);
+}
+
+export function errorName(err: any): string | undefined {
+  if (err instanceof anchor.AnchorError) {
+    return err.error.errorCode.code;
+  }
+  const custom = /"Custom":(\d+)/.exec(String(err?.message ?? err));
+  if (custom) {
+    const code = Number(custom[1]);
+    return program.idl.errors?.find((e) => e.code === code)?.name;
+  }
+  return undefined;
+}